@@ -0,0 +1,220 @@
+// Re-records the fixtures under tests/fixtures from the live API, then
+// `cargo test --test models` shows whether the models still fit.
+//
+// Public endpoints and streams are always recorded. Account endpoints are
+// recorded when BINANCE_API_KEY and BINANCE_API_SECRET are set. User data
+// events and the retired wapi endpoints are kept as they are.
+
+use binance_async as binance;
+
+use failure::{format_err, Fallible};
+use futures::prelude::*;
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+use tokio::time::timeout;
+use tokio_tungstenite::connect_async;
+
+use crate::binance::{Credentials, HmacSigner, Signer};
+
+const REST_URL: &str = "https://api.binance.com";
+const STREAM_URL: &str = "wss://stream.binance.com:9443/stream";
+const SYMBOL: &str = "ETHBTC";
+
+// Longest array kept, e.g. of prices or balances
+const MAX_ITEMS: usize = 20;
+
+// Values replaced wherever they appear
+const SCRUBBED: &[&str] = &[
+    "listenKey",
+    "clientOrderId",
+    "origClientOrderId",
+    "address",
+    "addressTag",
+    "txId",
+    "uid",
+];
+
+// Balances are recorded, but not their amounts
+const AMOUNTS: &[&str] = &["free", "locked"];
+
+fn anonymise(value: &mut Value) {
+    match value {
+        Value::Array(items) => {
+            items.truncate(MAX_ITEMS);
+            items.iter_mut().for_each(anonymise);
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if SCRUBBED.contains(&key.as_str()) && !field.is_null() {
+                    *field = Value::String(format!("anonymised{}", key));
+                } else if AMOUNTS.contains(&key.as_str()) {
+                    *field = Value::String("1.00000000".to_string());
+                } else {
+                    anonymise(field);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn write(name: &str, mut value: Value) -> Fallible<()> {
+    anonymise(&mut value);
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::write(&path, serde_json::to_string_pretty(&value)? + "\n")?;
+    println!("Recorded {}", name);
+    Ok(())
+}
+
+async fn get(
+    client: &reqwest::Client,
+    path: &str,
+    query: &str,
+    signer: Option<&HmacSigner>,
+) -> Fallible<Value> {
+    let request = match signer {
+        Some(signer) => {
+            let query = format!(
+                "{}{}timestamp={}",
+                query,
+                if query.is_empty() { "" } else { "&" },
+                chrono::Utc::now().timestamp_millis()
+            );
+            let signature = signer.sign(query.as_bytes())?;
+            client
+                .get(&format!(
+                    "{}{}?{}&signature={}",
+                    REST_URL, path, query, signature
+                ))
+                .header("X-MBX-APIKEY", signer.api_key())
+        }
+        None => client.get(&format!("{}{}?{}", REST_URL, path, query)),
+    };
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(format_err!("{} answered {}", path, response.status()));
+    }
+    Ok(response.json().await?)
+}
+
+async fn record_rest(client: &reqwest::Client) -> Fallible<()> {
+    let symbol = format!("symbol={}", SYMBOL);
+    let public = [
+        ("rest/ping.json", "/api/v3/ping", String::new()),
+        ("rest/time.json", "/api/v3/time", String::new()),
+        (
+            "rest/exchange_info.json",
+            "/api/v3/exchangeInfo",
+            symbol.clone(),
+        ),
+        (
+            "rest/depth.json",
+            "/api/v3/depth",
+            format!("{}&limit=5", symbol),
+        ),
+        (
+            "rest/all_prices.json",
+            "/api/v3/ticker/price",
+            String::new(),
+        ),
+        (
+            "rest/all_book_tickers.json",
+            "/api/v3/ticker/bookTicker",
+            String::new(),
+        ),
+        (
+            "rest/ticker_24hr.json",
+            "/api/v3/ticker/24hr",
+            symbol.clone(),
+        ),
+    ];
+    for (name, path, query) in &public {
+        write(name, get(client, path, query, None).await?)?;
+    }
+
+    let signer = match Credentials::from_env() {
+        Ok(credentials) => HmacSigner::from(credentials),
+        Err(_) => {
+            println!("No credentials, account fixtures left as they are");
+            return Ok(());
+        }
+    };
+    write(
+        "rest/account.json",
+        get(client, "/api/v3/account", "", Some(&signer)).await?,
+    )?;
+    // Empty lists say nothing about the schema
+    let signed = [
+        (
+            "rest/open_orders.json",
+            "/api/v3/openOrders",
+            symbol.clone(),
+        ),
+        (
+            "rest/my_trades.json",
+            "/api/v3/myTrades",
+            format!("{}&limit=1", symbol),
+        ),
+    ];
+    for (name, path, query) in &signed {
+        match get(client, path, query, Some(&signer)).await? {
+            Value::Array(items) if items.is_empty() => println!("Nothing to record for {}", name),
+            value => write(name, value)?,
+        }
+    }
+    Ok(())
+}
+
+async fn record_streams() -> Fallible<()> {
+    let symbol = SYMBOL.to_lowercase();
+    let streams: BTreeMap<String, &str> = vec![
+        (format!("{}@aggTrade", symbol), "stream/agg_trade.json"),
+        (format!("{}@trade", symbol), "stream/trade.json"),
+        (format!("{}@kline_1m", symbol), "stream/kline.json"),
+        (format!("{}@miniTicker", symbol), "stream/mini_ticker.json"),
+        ("!miniTicker@arr".to_string(), "stream/mini_ticker_all.json"),
+        (format!("{}@ticker", symbol), "stream/ticker.json"),
+        (format!("{}@depth", symbol), "stream/depth_update.json"),
+        (format!("{}@depth5", symbol), "stream/partial_depth.json"),
+        (format!("{}@bookTicker", symbol), "stream/book_ticker.json"),
+    ]
+    .into_iter()
+    .collect();
+
+    let names: Vec<_> = streams.keys().cloned().collect();
+    let url = format!("{}?streams={}", STREAM_URL, names.join("/"));
+    let (mut socket, _) = connect_async(url.as_str()).await?;
+
+    let mut pending = streams;
+    let recording = async {
+        while let Some(msg) = socket.try_next().await? {
+            let msg: Value = match msg.into_text() {
+                Ok(text) => serde_json::from_str(&text)?,
+                Err(_) => continue,
+            };
+            let stream = msg["stream"].as_str().unwrap_or_default().to_string();
+            if let Some(name) = pending.remove(&stream) {
+                write(name, msg["data"].clone())?;
+            }
+            if pending.is_empty() {
+                break;
+            }
+        }
+        Ok::<_, failure::Error>(())
+    };
+    timeout(Duration::from_secs(120), recording)
+        .await
+        .map_err(|_| format_err!("Streams without events: {:?}", pending.keys()))??;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Fallible<()> {
+    let client = reqwest::Client::new();
+    record_rest(&client).await?;
+    record_streams().await?;
+    Ok(())
+}
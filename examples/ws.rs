@@ -1,4 +1,7 @@
-use crate::binance::{model::websocket::Subscription, Binance, BinanceWebsocket};
+use crate::binance::{
+    model::websocket::{DepthLevel, Subscription, UpdateSpeed},
+    Binance, BinanceWebsocket, UserStreamKind,
+};
 use binance_async as binance;
 use failure::Fallible;
 use std::env::var;
@@ -12,27 +15,30 @@ async fn main() -> Fallible<()> {
     let api_secret_user = var("BINANCE_SECRET")?;
 
     let bn = Binance::with_credential(&api_key_user, &api_secret_user);
-    match bn.user_stream_start()?.await {
+    match bn.user_stream_start(&UserStreamKind::Spot)?.await {
         Ok(answer) => {
             println!("Data Stream Started ...");
             let listen_key = answer.listen_key;
 
             let mut ws = BinanceWebsocket::default();
 
-            for sub in vec![
+            ws.subscribe_many(vec![
                 Subscription::Ticker("ethbtc".to_string()),
                 Subscription::AggregateTrade("eosbtc".to_string()),
                 Subscription::Candlestick("ethbtc".to_string(), "1m".to_string()),
-                Subscription::Depth("xrpbtc".to_string()),
+                Subscription::Depth("xrpbtc".to_string(), UpdateSpeed::Millis100),
                 Subscription::MiniTicker("zrxbtc".to_string()),
-                Subscription::OrderBook("trxbtc".to_string(), 5),
+                Subscription::OrderBook(
+                    "trxbtc".to_string(),
+                    DepthLevel::Five,
+                    UpdateSpeed::Millis1000,
+                ),
                 Subscription::Trade("adabtc".to_string()),
                 Subscription::UserData(listen_key),
                 Subscription::MiniTickerAll,
                 Subscription::TickerAll,
-            ] {
-                ws.subscribe(sub).await?;
-            }
+            ])
+            .await?;
 
             while let Some(msg) = ws.try_next().await? {
                 println!("{:?}", msg)
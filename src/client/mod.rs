@@ -1,12 +1,27 @@
 mod account;
 mod general;
 mod market;
-mod userstream;
+pub mod multiplexer;
+pub mod orderbook;
+pub mod paper;
+pub mod traits;
+pub mod userstream;
 pub mod websocket;
+pub mod wsapi;
 
-use crate::transport::Transport;
+use crate::{
+    config::ClientConfig,
+    credentials::Credentials,
+    middleware::Middleware,
+    pool::ClientPool,
+    response::{Response, ResponseMeta},
+    signer::Signer,
+    transport::{RequestOptions, Transport},
+};
+use failure::Fallible;
+use futures::prelude::*;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Binance {
     pub transport: Transport,
 }
@@ -23,4 +38,94 @@ impl Binance {
             transport: Transport::with_credential(api_key, api_secret),
         }
     }
+
+    #[must_use]
+    pub fn with_credentials(credentials: Credentials) -> Self {
+        Self {
+            transport: Transport::with_credentials(credentials),
+        }
+    }
+
+    // Client that sends every request with the given options, e.g.
+    // `binance.with_options(RequestOptions::new().recv_window(1000)?).limit_buy(..)`
+    #[must_use]
+    pub fn with_options(&self, options: RequestOptions) -> Self {
+        Self {
+            transport: self.transport.with_options(options),
+        }
+    }
+
+    // Client sending REST requests with a preconfigured reqwest client
+    #[must_use]
+    pub fn with_client(&self, client: reqwest::Client) -> Self {
+        Self {
+            transport: self.transport.with_client(client),
+        }
+    }
+
+    // Client spreading REST requests over several clients, e.g. one per egress IP
+    #[must_use]
+    pub fn with_pool(&self, pool: ClientPool) -> Self {
+        Self {
+            transport: self.transport.with_pool(pool),
+        }
+    }
+
+    // Client with timeouts, proxy and pooling applied to REST requests and,
+    // where they apply, to websockets opened through it. Fails after `with_pool`,
+    // apply the config first.
+    pub fn with_config(&self, config: ClientConfig) -> Fallible<Self> {
+        Ok(Self {
+            transport: self.transport.with_config(config)?,
+        })
+    }
+
+    // Client that runs every request through the middleware, e.g. a
+    // `middleware::TimingMiddleware` shared through an `Arc`
+    #[must_use]
+    pub fn with_middleware<M: Middleware + 'static>(&self, middleware: M) -> Self {
+        Self {
+            transport: self.transport.with_middleware(middleware),
+        }
+    }
+
+    // Runs a call on a copy of the client that keeps the metadata of its own
+    // response, e.g. `binance.with_meta(|b| b.get_account()).await?.meta.used_weight_1m`.
+    // For calls sending several requests this is the metadata of the last one.
+    pub fn with_meta<F, T>(
+        &self,
+        call: impl FnOnce(&Self) -> Fallible<F>,
+    ) -> impl Future<Output = Fallible<Response<T>>>
+    where
+        F: Future<Output = Fallible<T>>,
+    {
+        let client = Self {
+            transport: self.transport.with_own_meta(),
+        };
+        let data = call(&client);
+
+        async move {
+            let data = data?.await?;
+            let meta = client
+                .last_response_meta()
+                .ok_or_else(|| failure::format_err!("No response received"))?;
+            Ok(Response { meta, data })
+        }
+    }
+
+    // Rate limit usage and latency of the latest REST response of this client
+    // or any of its clones. Best effort only, concurrent requests overwrite it;
+    // use `with_meta` for the response of a particular call.
+    #[must_use]
+    pub fn last_response_meta(&self) -> Option<ResponseMeta> {
+        self.transport.last_response_meta()
+    }
+
+    // Signs requests with an Ed25519, RSA or external key
+    #[must_use]
+    pub fn with_signer<S: Signer + 'static>(signer: S) -> Self {
+        Self {
+            transport: Transport::with_signer(signer),
+        }
+    }
 }
@@ -0,0 +1,532 @@
+use crate::{
+    client::websocket::{BinanceWebsocket, Routed},
+    error::Error,
+    model::{
+        websocket::{
+            AggregateTrade, BinanceWebsocketMessage, BookTickerUpdate, CandelStickMessage, Depth,
+            MiniTicker, Subscription, Ticker, TradeMessage, UserDataEvent,
+        },
+        OrderBook,
+    },
+};
+use failure::Fallible;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::Either,
+    prelude::*,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll, Waker},
+};
+use tokio::runtime::Handle;
+use tracing::*;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+// What to do when a subscriber does not keep up with its stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    DropOldest,
+    DropNewest,
+    // End the subscriber's stream with a `SubscriberLagged` error
+    Disconnect,
+}
+
+// Extracts a typed payload from the multiplexed message stream
+pub trait FromMessage: Sized + Send + 'static {
+    fn from_message(message: BinanceWebsocketMessage) -> Option<Self>;
+}
+
+impl FromMessage for BinanceWebsocketMessage {
+    fn from_message(message: BinanceWebsocketMessage) -> Option<Self> {
+        Some(message)
+    }
+}
+
+macro_rules! impl_from_message {
+    ($($variant:ident => $ty:ty),* $(,)?) => {
+        $(
+            impl FromMessage for $ty {
+                fn from_message(message: BinanceWebsocketMessage) -> Option<Self> {
+                    match message {
+                        BinanceWebsocketMessage::$variant(payload) => Some(payload),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_message! {
+    UserData => UserDataEvent,
+    AggregateTrade => AggregateTrade,
+    Trade => TradeMessage,
+    Candlestick => CandelStickMessage,
+    MiniTicker => MiniTicker,
+    MiniTickerAll => Vec<MiniTicker>,
+    Ticker => Ticker,
+    TickerAll => Vec<Ticker>,
+    OrderBook => OrderBook,
+    Depth => Depth,
+    BookTicker => BookTickerUpdate,
+}
+
+// Bounded buffer between the connection manager and one subscriber
+struct Queue {
+    items: VecDeque<Fallible<BinanceWebsocketMessage>>,
+    capacity: usize,
+    policy: LagPolicy,
+    dropped: u64,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Queue {
+    const fn new(capacity: usize, policy: LagPolicy) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity,
+            policy,
+            dropped: 0,
+            closed: false,
+            waker: None,
+        }
+    }
+
+    fn push(&mut self, item: Fallible<BinanceWebsocketMessage>) {
+        if self.closed {
+            return;
+        }
+
+        if self.items.len() >= self.capacity {
+            self.dropped += 1;
+            match self.policy {
+                LagPolicy::DropOldest => {
+                    self.items.pop_front();
+                    self.items.push_back(item);
+                }
+                LagPolicy::DropNewest => {}
+                LagPolicy::Disconnect => {
+                    self.items.clear();
+                    self.items.push_back(Err(Error::SubscriberLagged {
+                        capacity: self.capacity,
+                    }
+                    .into()));
+                    self.close();
+                    return;
+                }
+            }
+        } else {
+            self.items.push_back(item);
+        }
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+type SharedQueue = Arc<Mutex<Queue>>;
+
+// A subscriber panicking mid-poll leaves the queue usable
+fn lock(queue: &SharedQueue) -> MutexGuard<'_, Queue> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+enum Command {
+    Subscribe {
+        id: u64,
+        subscription: Subscription,
+        queue: SharedQueue,
+        reply: oneshot::Sender<Fallible<()>>,
+    },
+    Unsubscribe {
+        id: u64,
+        subscription: Subscription,
+    },
+}
+
+// Cloneable handle to a websocket connection manager running on the tokio runtime.
+// Every subscriber gets its own bounded stream of messages.
+#[derive(Clone)]
+pub struct WebsocketHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    next_id: Arc<AtomicU64>,
+    capacity: usize,
+    policy: LagPolicy,
+}
+
+impl BinanceWebsocket {
+    // Moves the websocket onto a background task shared by typed subscribers.
+    // Fails outside a tokio runtime.
+    pub fn spawn(self) -> Fallible<WebsocketHandle> {
+        let runtime = Handle::try_current()
+            .map_err(|_| failure::format_err!("No tokio runtime to run the websocket on"))?;
+        let (commands, receiver) = mpsc::unbounded();
+        runtime.spawn(run(self, receiver));
+
+        Ok(WebsocketHandle {
+            commands,
+            next_id: Arc::new(AtomicU64::new(0)),
+            capacity: DEFAULT_CAPACITY,
+            policy: LagPolicy::DropOldest,
+        })
+    }
+}
+
+impl WebsocketHandle {
+    pub fn new() -> Fallible<Self> {
+        BinanceWebsocket::default().spawn()
+    }
+
+    // Buffer size and lag policy used by subsequent subscriptions
+    #[must_use]
+    pub const fn with_buffer(mut self, capacity: usize, policy: LagPolicy) -> Self {
+        self.capacity = capacity;
+        self.policy = policy;
+        self
+    }
+
+    pub async fn subscribe_typed<T: FromMessage>(
+        &self,
+        subscription: Subscription,
+    ) -> Fallible<TypedStream<T>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(Mutex::new(Queue::new(self.capacity.max(1), self.policy)));
+        let (reply, response) = oneshot::channel();
+
+        self.commands
+            .unbounded_send(Command::Subscribe {
+                id,
+                subscription: subscription.clone(),
+                queue: queue.clone(),
+                reply,
+            })
+            .map_err(|_| failure::format_err!("Websocket manager stopped"))?;
+        response
+            .await
+            .map_err(|_| failure::format_err!("Websocket manager stopped"))??;
+
+        Ok(TypedStream {
+            id,
+            subscription,
+            queue,
+            commands: self.commands.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    pub async fn subscribe(
+        &self,
+        subscription: Subscription,
+    ) -> Fallible<TypedStream<BinanceWebsocketMessage>> {
+        self.subscribe_typed(subscription).await
+    }
+}
+
+// Connection events reach typed subscribers as errors, so they can tell
+// that messages may have been missed
+const fn interruption(message: &BinanceWebsocketMessage) -> Option<Error> {
+    match message {
+        BinanceWebsocketMessage::Disconnected(_) => Some(Error::StreamDisconnected),
+        BinanceWebsocketMessage::Reconnected(_) => Some(Error::StreamReconnected),
+        BinanceWebsocketMessage::Stale(_) => Some(Error::StreamStale),
+        _ => None,
+    }
+}
+
+// Messages of a single subscription. Unsubscribes when dropped.
+pub struct TypedStream<T> {
+    id: u64,
+    subscription: Subscription,
+    queue: SharedQueue,
+    commands: mpsc::UnboundedSender<Command>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedStream<T> {
+    #[must_use]
+    pub const fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    // Messages discarded because the buffer was full
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        lock(&self.queue).dropped
+    }
+}
+
+impl<T: FromMessage> Stream for TypedStream<T> {
+    type Item = Fallible<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = lock(&self.queue);
+
+        while let Some(item) = queue.items.pop_front() {
+            match item {
+                Ok(message) => {
+                    let interruption = interruption(&message);
+                    if let Some(payload) = T::from_message(message) {
+                        return Poll::Ready(Some(Ok(payload)));
+                    }
+                    if let Some(e) = interruption {
+                        return Poll::Ready(Some(Err(e.into())));
+                    }
+                }
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for TypedStream<T> {
+    fn drop(&mut self) {
+        let _ = self.commands.unbounded_send(Command::Unsubscribe {
+            id: self.id,
+            subscription: self.subscription.clone(),
+        });
+    }
+}
+
+#[derive(Default)]
+struct Subscribers {
+    // Stream name -> subscriber queues
+    queues: HashMap<String, Vec<(u64, SharedQueue)>>,
+}
+
+impl Subscribers {
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    fn send(&self, subscription: &Subscription, item: &Fallible<BinanceWebsocketMessage>) {
+        if let Some(queues) = self.queues.get(&subscription.stream_name()) {
+            for (_, queue) in queues {
+                lock(queue).push(clone_item(item));
+            }
+        }
+    }
+
+    fn broadcast(&self, item: &Fallible<BinanceWebsocketMessage>) {
+        for queues in self.queues.values() {
+            for (_, queue) in queues {
+                lock(queue).push(clone_item(item));
+            }
+        }
+    }
+
+    // Removes the subscriber, returns true if it was the last one on its stream
+    fn remove(&mut self, id: u64, subscription: &Subscription) -> bool {
+        let name = subscription.stream_name();
+        if let Some(queues) = self.queues.get_mut(&name) {
+            queues.retain(|(queue_id, _)| *queue_id != id);
+            if queues.is_empty() {
+                self.queues.remove(&name);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn clone_item(item: &Fallible<BinanceWebsocketMessage>) -> Fallible<BinanceWebsocketMessage> {
+    match item {
+        Ok(message) => Ok(message.clone()),
+        Err(e) => Err(failure::format_err!("{}", e)),
+    }
+}
+
+async fn run(mut websocket: BinanceWebsocket, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut subscribers = Subscribers::default();
+
+    loop {
+        // Only poll the websocket while something is subscribed
+        let next = if subscribers.is_empty() {
+            Either::Left(commands.next().await)
+        } else {
+            let routed = future::poll_fn(|cx| websocket.poll_routed(cx));
+            match future::select(commands.next(), routed).await {
+                Either::Left((command, _)) => Either::Left(command),
+                Either::Right((routed, _)) => Either::Right(routed),
+            }
+        };
+
+        match next {
+            Either::Left(Some(Command::Subscribe {
+                id,
+                subscription,
+                queue,
+                reply,
+            })) => {
+                let name = subscription.stream_name();
+                let result = if subscribers.queues.contains_key(&name) {
+                    Ok(())
+                } else {
+                    websocket.subscribe(subscription).await
+                };
+                if result.is_ok() {
+                    subscribers
+                        .queues
+                        .entry(name)
+                        .or_default()
+                        .push((id, queue));
+                }
+                let _ = reply.send(result);
+            }
+            Either::Left(Some(Command::Unsubscribe { id, subscription })) => {
+                if subscribers.remove(id, &subscription) {
+                    if let Err(e) = websocket.unsubscribe(&subscription).await {
+                        warn!(
+                            "[Websocket] Failed to unsubscribe '{:?}': {}",
+                            subscription, e
+                        );
+                    }
+                }
+            }
+            // Every handle and stream is gone
+            Either::Left(None) => return,
+            Either::Right(Some(routed)) => route(&subscribers, routed),
+            Either::Right(None) => {
+                for queues in subscribers.queues.values() {
+                    for (_, queue) in queues {
+                        lock(queue).close();
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn route(subscribers: &Subscribers, routed: Routed) {
+    match routed {
+        (Some(subscription), item) => subscribers.send(&subscription, &item),
+        // Connection events are split per subscription
+        (None, Ok(BinanceWebsocketMessage::Disconnected(subscriptions))) => {
+            for subscription in subscriptions {
+                let event = BinanceWebsocketMessage::Disconnected(vec![subscription.clone()]);
+                subscribers.send(&subscription, &Ok(event));
+            }
+        }
+        (None, Ok(BinanceWebsocketMessage::Reconnected(subscriptions))) => {
+            for subscription in subscriptions {
+                let event = BinanceWebsocketMessage::Reconnected(vec![subscription.clone()]);
+                subscribers.send(&subscription, &Ok(event));
+            }
+        }
+        (None, Ok(BinanceWebsocketMessage::Stale(subscription))) => {
+            let event = BinanceWebsocketMessage::Stale(subscription.clone());
+            subscribers.send(&subscription, &Ok(event));
+        }
+        (None, Ok(_)) => {}
+        (None, item @ Err(_)) => subscribers.broadcast(&item),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FromMessage, LagPolicy, Queue, TypedStream};
+    use crate::{
+        client::websocket::BinanceWebsocket,
+        error::Error,
+        model::websocket::{BinanceWebsocketMessage, Subscription, Ticker},
+    };
+    use futures::{channel::mpsc, executor::block_on, prelude::*};
+    use std::{
+        marker::PhantomData,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn lag_policies() {
+        let ping = || Ok(BinanceWebsocketMessage::Ping);
+
+        let mut queue = Queue::new(2, LagPolicy::DropOldest);
+        for _ in 0..3 {
+            queue.push(ping());
+        }
+        assert_eq!(queue.items.len(), 2);
+        assert_eq!(queue.dropped, 1);
+
+        let mut queue = Queue::new(2, LagPolicy::DropNewest);
+        queue.push(Ok(BinanceWebsocketMessage::Pong));
+        queue.push(ping());
+        queue.push(ping());
+        assert!(matches!(
+            queue.items.front(),
+            Some(Ok(BinanceWebsocketMessage::Pong))
+        ));
+        assert_eq!(queue.items.len(), 2);
+
+        let mut queue = Queue::new(2, LagPolicy::Disconnect);
+        queue.push(ping());
+        queue.push(ping());
+        queue.push(ping());
+        assert!(queue.closed);
+        assert_eq!(queue.items.len(), 1);
+        assert!(queue.items[0].is_err());
+    }
+
+    #[test]
+    fn typed_extraction() {
+        let stale = BinanceWebsocketMessage::Stale(Subscription::TickerAll);
+        assert!(Ticker::from_message(stale.clone()).is_none());
+        assert!(BinanceWebsocketMessage::from_message(stale).is_some());
+    }
+
+    #[test]
+    fn typed_interruptions() {
+        let queue = Arc::new(Mutex::new(Queue::new(8, LagPolicy::DropOldest)));
+        let (commands, _receiver) = mpsc::unbounded();
+        let mut stream = TypedStream::<Ticker> {
+            id: 0,
+            subscription: Subscription::TickerAll,
+            queue: queue.clone(),
+            commands,
+            _marker: PhantomData,
+        };
+        {
+            let mut queue = queue.lock().unwrap();
+            queue.push(Ok(BinanceWebsocketMessage::Disconnected(vec![])));
+            queue.push(Ok(BinanceWebsocketMessage::Pong));
+            queue.push(Ok(BinanceWebsocketMessage::Reconnected(vec![])));
+            queue.close();
+        }
+
+        let errors: Vec<_> = block_on(stream.by_ref().collect::<Vec<_>>())
+            .into_iter()
+            .map(|item| item.unwrap_err().downcast::<Error>().unwrap())
+            .collect();
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::StreamDisconnected, Error::StreamReconnected]
+        ));
+    }
+
+    #[test]
+    fn spawn_needs_runtime() {
+        assert!(BinanceWebsocket::default().spawn().is_err());
+    }
+}
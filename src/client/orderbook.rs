@@ -0,0 +1,454 @@
+use crate::{
+    client::{websocket::BinanceWebsocket, Binance},
+    error::Error,
+    model::{
+        websocket::{BinanceWebsocketMessage, Depth, Subscription, UpdateSpeed},
+        OrderBook,
+    },
+};
+use failure::Fallible;
+use futures::prelude::*;
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::delay_for;
+use tracing::*;
+
+// Snapshot depth recommended by Binance for local order book management
+const SNAPSHOT_LIMIT: u64 = 1000;
+// Weight 50 a piece, failed snapshots are retried with an exponential backoff
+const MAX_SNAPSHOT_BACKOFF: Duration = Duration::from_secs(30);
+
+type SnapshotFuture = Pin<Box<dyn Future<Output = Fallible<OrderBook>> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopOfBook {
+    pub best_bid: Option<(f64, f64)>, // price, qty
+    pub best_ask: Option<(f64, f64)>, // price, qty
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Price(f64);
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+enum SyncState {
+    // Waiting for the REST snapshot, diff events are buffered meanwhile
+    Syncing {
+        snapshot: SnapshotFuture,
+        buffer: Vec<Depth>,
+    },
+    Synced,
+    // The depth stream is reconnecting, the book is stale
+    Disconnected,
+    // The snapshot was rejected for good, e.g. for an unknown symbol
+    Failed,
+}
+
+#[derive(Debug, PartialEq)]
+enum ApplyResult {
+    Stale,
+    Gap,
+    Applied,
+}
+
+// Order book kept in sync with the depth diff stream.
+// Yields the top of the book every time it changes.
+pub struct LocalOrderBook {
+    symbol: String,
+    binance: Binance,
+    websocket: BinanceWebsocket,
+    state: SyncState,
+    bids: BTreeMap<Price, f64>,
+    asks: BTreeMap<Price, f64>,
+    last_update_id: u64,
+    top: Option<TopOfBook>,
+    // Snapshots failed in a row
+    failures: u32,
+}
+
+impl LocalOrderBook {
+    pub async fn new(binance: Binance, symbol: &str) -> Fallible<Self> {
+        let mut websocket = BinanceWebsocket::with_config(binance.transport.config().clone());
+        websocket
+            .subscribe(Subscription::Depth(
+                symbol.to_lowercase(),
+                UpdateSpeed::Millis100,
+            ))
+            .await?;
+
+        let mut book = Self {
+            symbol: symbol.to_uppercase(),
+            binance,
+            websocket,
+            state: SyncState::Synced,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id: 0,
+            top: None,
+            failures: 0,
+        };
+        book.resync(Vec::new());
+
+        Ok(book)
+    }
+
+    #[must_use]
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    #[must_use]
+    pub const fn is_synced(&self) -> bool {
+        matches!(self.state, SyncState::Synced)
+    }
+
+    #[must_use]
+    pub const fn last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+
+    // Bids from the best (highest) price down
+    pub fn bids(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.bids.iter().rev().map(|(price, qty)| (price.0, *qty))
+    }
+
+    // Asks from the best (lowest) price up
+    pub fn asks(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.asks.iter().map(|(price, qty)| (price.0, *qty))
+    }
+
+    #[must_use]
+    pub fn best_bid(&self) -> Option<(f64, f64)> {
+        self.bids().next()
+    }
+
+    #[must_use]
+    pub fn best_ask(&self) -> Option<(f64, f64)> {
+        self.asks().next()
+    }
+
+    #[must_use]
+    pub fn top_of_book(&self) -> TopOfBook {
+        TopOfBook {
+            best_bid: self.best_bid(),
+            best_ask: self.best_ask(),
+        }
+    }
+
+    fn snapshot(&self, delay: Duration) -> SnapshotFuture {
+        let snapshot = self.binance.get_depth(&self.symbol, SNAPSHOT_LIMIT);
+        Box::pin(async move {
+            if delay > Duration::from_secs(0) {
+                delay_for(delay).await;
+            }
+            snapshot?.await
+        })
+    }
+
+    fn resync(&mut self, buffer: Vec<Depth>) {
+        let delay = snapshot_backoff(self.failures);
+        debug!(
+            "[OrderBook] Resynchronising '{}' in {:?}",
+            self.symbol, delay
+        );
+        self.state = SyncState::Syncing {
+            snapshot: self.snapshot(delay),
+            buffer,
+        };
+    }
+
+    fn load_snapshot(&mut self, snapshot: &OrderBook, buffer: Vec<Depth>) {
+        self.bids = snapshot
+            .bids
+            .iter()
+            .filter(|bid| bid.qty > 0.)
+            .map(|bid| (Price(bid.price), bid.qty))
+            .collect();
+        self.asks = snapshot
+            .asks
+            .iter()
+            .filter(|ask| ask.qty > 0.)
+            .map(|ask| (Price(ask.price), ask.qty))
+            .collect();
+        self.last_update_id = snapshot.last_update_id;
+        self.state = SyncState::Synced;
+        self.failures = 0;
+
+        let mut buffer = buffer.into_iter();
+        while let Some(depth) = buffer.next() {
+            if self.apply(&depth) == ApplyResult::Gap {
+                // The snapshot predates the first buffered event, fetch a newer one
+                self.resync(std::iter::once(depth).chain(buffer).collect());
+                return;
+            }
+        }
+    }
+
+    fn on_depth(&mut self, depth: Depth) {
+        match &mut self.state {
+            SyncState::Syncing { buffer, .. } => buffer.push(depth),
+            SyncState::Disconnected | SyncState::Failed => {}
+            SyncState::Synced => {
+                if self.apply(&depth) == ApplyResult::Gap {
+                    warn!(
+                        "[OrderBook] Gap in '{}' depth stream: expected {}, got {}",
+                        self.symbol,
+                        self.last_update_id + 1,
+                        depth.first_update_id
+                    );
+                    self.resync(vec![depth]);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, depth: &Depth) -> ApplyResult {
+        if depth.final_update_id <= self.last_update_id {
+            return ApplyResult::Stale;
+        }
+        if depth.first_update_id > self.last_update_id + 1 {
+            return ApplyResult::Gap;
+        }
+
+        for bid in &depth.bids {
+            update_level(&mut self.bids, bid.price, bid.qty);
+        }
+        for ask in &depth.asks {
+            update_level(&mut self.asks, ask.price, ask.qty);
+        }
+        self.last_update_id = depth.final_update_id;
+
+        ApplyResult::Applied
+    }
+
+    fn take_top_change(&mut self) -> Option<TopOfBook> {
+        if !self.is_synced() {
+            return None;
+        }
+
+        let top = self.top_of_book();
+        if self.top == Some(top) {
+            None
+        } else {
+            self.top = Some(top);
+            Some(top)
+        }
+    }
+}
+
+// Immediate after gaps and reconnects, backing off while snapshots keep failing
+fn snapshot_backoff(failures: u32) -> Duration {
+    match failures {
+        0 => Duration::from_secs(0),
+        n => MAX_SNAPSHOT_BACKOFF.min(Duration::from_secs(1 << (n - 1).min(6))),
+    }
+}
+
+// Rate limits, bans and server trouble pass, other rejections would recur on
+// every retry
+fn is_permanent(e: &failure::Error) -> bool {
+    match e.downcast_ref::<Error>() {
+        Some(Error::BinanceError { code, .. }) => {
+            !matches!(code, -1000 | -1001 | -1003 | -1006 | -1007 | -1008)
+        }
+        _ => false,
+    }
+}
+
+fn update_level(side: &mut BTreeMap<Price, f64>, price: f64, qty: f64) {
+    if qty > 0. {
+        side.insert(Price(price), qty);
+    } else {
+        side.remove(&Price(price));
+    }
+}
+
+impl Stream for LocalOrderBook {
+    type Item = Fallible<TopOfBook>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if matches!(this.state, SyncState::Failed) {
+                return Poll::Ready(None);
+            }
+            if let SyncState::Syncing { snapshot, buffer } = &mut this.state {
+                match snapshot.as_mut().poll(cx) {
+                    Poll::Ready(Ok(snapshot)) => {
+                        let buffer = mem::take(buffer);
+                        this.load_snapshot(&snapshot, buffer);
+                        if let Some(top) = this.take_top_change() {
+                            return Poll::Ready(Some(Ok(top)));
+                        }
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        if is_permanent(&e) {
+                            warn!("[OrderBook] Snapshot of '{}' failed: {}", this.symbol, e);
+                            this.state = SyncState::Failed;
+                        } else {
+                            let buffer = mem::take(buffer);
+                            this.failures += 1;
+                            this.resync(buffer);
+                        }
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            match Pin::new(&mut this.websocket).poll_next(cx) {
+                Poll::Ready(Some(Ok(BinanceWebsocketMessage::Depth(depth)))) => {
+                    this.on_depth(depth);
+                    if let Some(top) = this.take_top_change() {
+                        return Poll::Ready(Some(Ok(top)));
+                    }
+                }
+                Poll::Ready(Some(Ok(BinanceWebsocketMessage::Disconnected(..)))) => {
+                    this.state = SyncState::Disconnected;
+                }
+                Poll::Ready(Some(Ok(BinanceWebsocketMessage::Reconnected(..)))) => {
+                    this.resync(Vec::new());
+                }
+                Poll::Ready(Some(Ok(_))) => {}
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        is_permanent, snapshot_backoff, ApplyResult, LocalOrderBook, SyncState, TopOfBook,
+    };
+    use crate::{
+        client::{websocket::BinanceWebsocket, Binance},
+        error::Error,
+        model::{websocket::Depth, OrderBook},
+    };
+    use failure::Fallible;
+    use serde_json::{from_value, json};
+    use std::{collections::BTreeMap, time::Duration};
+
+    fn book() -> LocalOrderBook {
+        LocalOrderBook {
+            symbol: "BNBBTC".into(),
+            binance: Binance::new(),
+            websocket: BinanceWebsocket::default(),
+            state: SyncState::Synced,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id: 0,
+            top: None,
+            failures: 0,
+        }
+    }
+
+    fn depth(first: u64, last: u64, bids: &[[&str; 2]], asks: &[[&str; 2]]) -> Fallible<Depth> {
+        Ok(from_value(json!({
+            "e": "depthUpdate",
+            "E": 123_456_789,
+            "s": "BNBBTC",
+            "U": first,
+            "u": last,
+            "b": bids,
+            "a": asks,
+        }))?)
+    }
+
+    #[test]
+    fn snapshot_with_buffer() -> Fallible<()> {
+        let mut book = book();
+        let snapshot: OrderBook = from_value(json!({
+            "lastUpdateId": 100,
+            "bids": [["0.0024", "10"], ["0.0023", "5"]],
+            "asks": [["0.0026", "100"]],
+        }))?;
+        let buffer = vec![
+            depth(90, 100, &[["0.0024", "1"]], &[])?,
+            depth(95, 102, &[["0.0024", "0"]], &[["0.0025", "7"]])?,
+            depth(103, 104, &[], &[["0.0025", "3"]])?,
+        ];
+
+        book.load_snapshot(&snapshot, buffer);
+
+        assert!(book.is_synced());
+        assert_eq!(book.last_update_id(), 104);
+        assert_eq!(book.bids().collect::<Vec<_>>(), vec![(0.0023, 5.)]);
+        assert_eq!(
+            book.asks().collect::<Vec<_>>(),
+            vec![(0.0025, 3.), (0.0026, 100.)]
+        );
+        assert_eq!(
+            book.take_top_change(),
+            Some(TopOfBook {
+                best_bid: Some((0.0023, 5.)),
+                best_ask: Some((0.0025, 3.)),
+            })
+        );
+        assert_eq!(book.take_top_change(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn gap_triggers_resync() -> Fallible<()> {
+        let mut book = book();
+        book.last_update_id = 10;
+
+        assert_eq!(book.apply(&depth(5, 10, &[], &[])?), ApplyResult::Stale);
+        assert_eq!(book.apply(&depth(11, 12, &[], &[])?), ApplyResult::Applied);
+        assert_eq!(book.apply(&depth(14, 15, &[], &[])?), ApplyResult::Gap);
+
+        book.on_depth(depth(14, 15, &[], &[])?);
+        assert!(!book.is_synced());
+        assert_eq!(book.take_top_change(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_retries() {
+        let backoff: Vec<_> = (0..9).map(snapshot_backoff).collect();
+        assert_eq!(backoff[0], Duration::from_secs(0));
+        assert_eq!(backoff[1], Duration::from_secs(1));
+        assert_eq!(backoff[4], Duration::from_secs(8));
+        assert_eq!(backoff[8], Duration::from_secs(30));
+
+        let rejection = |code| {
+            failure::Error::from(Error::BinanceError {
+                code,
+                msg: String::new(),
+            })
+        };
+        assert!(is_permanent(&rejection(-1121)));
+        assert!(!is_permanent(&rejection(-1003)));
+        assert!(!is_permanent(&failure::format_err!("Socket closed")));
+    }
+}
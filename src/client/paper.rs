@@ -0,0 +1,641 @@
+use crate::{
+    client::{
+        multiplexer::{TypedStream, WebsocketHandle},
+        traits::{MarketData, Trading},
+        userstream::UserStreamKind,
+        websocket::BinanceWebsocket,
+        Binance,
+    },
+    engine::{Exchange, Rejection},
+    error::Error,
+    model::{
+        websocket::{BookTickerUpdate, Subscription, TradeMessage, UserDataEvent},
+        AccountInformation, Balance, BookTickers, ExchangeInfo, ExchangeInformation,
+        KlineSummaries, Order, OrderBook, OrderCanceled, PriceStats, Prices, ServerTime, Ticker,
+        TradeHistory, Transaction,
+    },
+};
+use async_trait::async_trait;
+use failure::Fallible;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    prelude::*,
+    stream,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+    task::{Context, Poll},
+};
+use tracing::*;
+
+fn rejected((_, code, msg): Rejection) -> failure::Error {
+    Error::BinanceError { code, msg }.into()
+}
+
+fn parse<T: DeserializeOwned>(value: Value) -> Fallible<T> {
+    Ok(serde_json::from_value(value)?)
+}
+
+fn order_params(
+    symbol: &str,
+    side: &str,
+    kind: &str,
+    qty: f64,
+    price: Option<f64>,
+) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    params.insert("symbol".to_string(), symbol.to_string());
+    params.insert("side".to_string(), side.to_string());
+    params.insert("type".to_string(), kind.to_string());
+    params.insert("quantity".to_string(), qty.to_string());
+    if let Some(price) = price {
+        params.insert("price".to_string(), price.to_string());
+        params.insert("timeInForce".to_string(), "GTC".to_string());
+    }
+    params
+}
+
+fn symbol_params(symbol: &str, order_id: Option<u64>) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    params.insert("symbol".to_string(), symbol.to_string());
+    if let Some(order_id) = order_id {
+        params.insert("orderId".to_string(), order_id.to_string());
+    }
+    params
+}
+
+struct Paper {
+    exchange: Exchange,
+    websocket: Option<WebsocketHandle>,
+    // User data streams by their listen key
+    streams: HashMap<String, UnboundedSender<Fallible<UserDataEvent>>>,
+}
+
+impl Paper {
+    // Sends pending exchange events to the user data streams
+    fn flush(&mut self) {
+        for (listen_key, event) in self.exchange.events.drain(..) {
+            if let Some(tx) = self.streams.get(&listen_key) {
+                if tx.unbounded_send(parse(event)).is_err() {
+                    self.streams.remove(&listen_key);
+                }
+            }
+        }
+    }
+}
+
+// Trading methods of `Binance` run against a simulated account. Orders fill
+// on the live book ticker and trade streams of their symbol, subscribed on the
+// first order, and user data events are emitted as Binance would send them.
+#[derive(Clone)]
+pub struct PaperBinance {
+    binance: Binance,
+    state: Arc<Mutex<Paper>>,
+}
+
+impl PaperBinance {
+    // Starts from the balances and commission rates of `account`, e.g. as
+    // returned by `Binance::get_account`. Market data comes from `binance`.
+    pub fn new(binance: Binance, account: &AccountInformation) -> Fallible<Self> {
+        let mut exchange = Exchange::default();
+        exchange.set_commission(
+            f64::from(account.maker_commission),
+            f64::from(account.taker_commission),
+        );
+        for balance in &account.balances {
+            let free = balance.free.parse().map_err(|e| {
+                failure::format_err!(
+                    "Invalid {} balance '{}': {}",
+                    balance.asset,
+                    balance.free,
+                    e
+                )
+            })?;
+            exchange.set_balance(&balance.asset, free);
+        }
+
+        Ok(Self {
+            binance,
+            state: Arc::new(Mutex::new(Paper {
+                exchange,
+                websocket: None,
+                streams: HashMap::new(),
+            })),
+        })
+    }
+
+    // Subscribes to market data through an existing websocket manager
+    // instead of spawning one on first use
+    #[must_use]
+    pub fn with_websocket(self, websocket: WebsocketHandle) -> Self {
+        self.lock().websocket = Some(websocket);
+        self
+    }
+
+    #[must_use]
+    pub const fn binance(&self) -> &Binance {
+        &self.binance
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Paper> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Trades `symbol` on quotes and trades fed through `quote` and `trade`
+    // rather than the live streams, e.g. to replay recorded market data
+    pub fn add_market(&self, symbol: &str, base: &str, quote: &str, bid: f64, ask: f64) {
+        let mut paper = self.lock();
+        paper
+            .exchange
+            .add_symbol(symbol, base, quote, f64::midpoint(bid, ask));
+        paper.exchange.quote(symbol, bid, ask);
+    }
+
+    // New best bid and ask, filling resting orders they cross
+    pub fn quote(&self, symbol: &str, bid: f64, ask: f64) {
+        let mut paper = self.lock();
+        paper.exchange.quote(symbol, bid, ask);
+        paper.flush();
+    }
+
+    // Trade printed by the market, filling resting orders it crosses
+    pub fn trade(&self, symbol: &str, price: f64) {
+        let mut paper = self.lock();
+        paper.exchange.trade(symbol, price);
+        paper.flush();
+    }
+
+    fn websocket(&self) -> Fallible<WebsocketHandle> {
+        let config = self.binance.transport.config().clone();
+        let mut paper = self.lock();
+        let websocket = if let Some(websocket) = &paper.websocket {
+            websocket.clone()
+        } else {
+            let websocket = BinanceWebsocket::with_config(config).spawn()?;
+            paper.websocket = Some(websocket.clone());
+            websocket
+        };
+        drop(paper);
+        Ok(websocket)
+    }
+
+    // Subscribes to the market data of `symbol` unless it is traded already
+    async fn ensure_market(&self, symbol: &str) -> Fallible<()> {
+        if self.lock().exchange.has_symbol(symbol) {
+            return Ok(());
+        }
+
+        let info = self.binance.exchange_info()?.await?;
+        let market = info
+            .symbols
+            .into_iter()
+            .find(|s| s.symbol == symbol)
+            .ok_or(Error::SymbolNotFound)?;
+        let ticker = self.binance.get_book_ticker(symbol)?.await?;
+        let websocket = self.websocket()?;
+        let quotes = websocket
+            .subscribe_typed::<BookTickerUpdate>(Subscription::BookTicker(symbol.to_string()))
+            .await?;
+        let trades = websocket
+            .subscribe_typed::<TradeMessage>(Subscription::Trade(symbol.to_string()))
+            .await?;
+
+        // Another order may have set the market up meanwhile
+        if self.lock().exchange.has_symbol(symbol) {
+            return Ok(());
+        }
+        self.add_market(
+            symbol,
+            &market.base_asset,
+            &market.quote_asset,
+            ticker.bid_price,
+            ticker.ask_price,
+        );
+        tokio::spawn(feed(Arc::downgrade(&self.state), quotes, trades));
+        Ok(())
+    }
+
+    async fn place(&self, params: HashMap<String, String>) -> Fallible<Transaction> {
+        self.ensure_market(&params["symbol"]).await?;
+        let mut paper = self.lock();
+        let transaction = paper.exchange.place(&params).map_err(rejected);
+        paper.flush();
+        drop(paper);
+        parse(transaction?)
+    }
+
+    pub fn get_account(&self) -> Fallible<impl Future<Output = Fallible<AccountInformation>>> {
+        let account = self.lock().exchange.account();
+        Ok(future::ready(parse(account)))
+    }
+
+    pub fn get_balance(&self, asset: &str) -> Fallible<impl Future<Output = Fallible<Balance>>> {
+        let asset = asset.to_string();
+        let balance = self
+            .get_account()?
+            .and_then(move |account: AccountInformation| {
+                let balance = account
+                    .balances
+                    .into_iter()
+                    .find(|balance| balance.asset == asset);
+                future::ready(balance.ok_or_else(|| Error::AssetsNotFound.into()))
+            });
+        Ok(balance)
+    }
+
+    pub fn get_open_orders(
+        &self,
+        symbol: &str,
+    ) -> Fallible<impl Future<Output = Fallible<Vec<Order>>>> {
+        let orders = self
+            .lock()
+            .exchange
+            .open_orders(&symbol_params(symbol, None));
+        Ok(future::ready(parse(orders)))
+    }
+
+    pub fn get_all_open_orders(&self) -> Fallible<impl Future<Output = Fallible<Vec<Order>>>> {
+        let orders = self.lock().exchange.open_orders(&HashMap::new());
+        Ok(future::ready(parse(orders)))
+    }
+
+    pub fn order_status(
+        &self,
+        symbol: &str,
+        order_id: u64,
+    ) -> Fallible<impl Future<Output = Fallible<Order>>> {
+        let order = self
+            .lock()
+            .exchange
+            .order(&symbol_params(symbol, Some(order_id)));
+        Ok(future::ready(order.map_err(rejected).and_then(parse)))
+    }
+
+    pub fn limit_buy(
+        &self,
+        symbol: &str,
+        qty: f64,
+        price: f64,
+    ) -> Fallible<impl Future<Output = Fallible<Transaction>>> {
+        let params = order_params(symbol, "BUY", "LIMIT", qty, Some(price));
+        let paper = self.clone();
+        Ok(async move { paper.place(params).await })
+    }
+
+    pub fn limit_sell(
+        &self,
+        symbol: &str,
+        qty: f64,
+        price: f64,
+    ) -> Fallible<impl Future<Output = Fallible<Transaction>>> {
+        let params = order_params(symbol, "SELL", "LIMIT", qty, Some(price));
+        let paper = self.clone();
+        Ok(async move { paper.place(params).await })
+    }
+
+    pub fn market_buy(
+        &self,
+        symbol: &str,
+        qty: f64,
+    ) -> Fallible<impl Future<Output = Fallible<Transaction>>> {
+        let params = order_params(symbol, "BUY", "MARKET", qty, None);
+        let paper = self.clone();
+        Ok(async move { paper.place(params).await })
+    }
+
+    pub fn market_sell(
+        &self,
+        symbol: &str,
+        qty: f64,
+    ) -> Fallible<impl Future<Output = Fallible<Transaction>>> {
+        let params = order_params(symbol, "SELL", "MARKET", qty, None);
+        let paper = self.clone();
+        Ok(async move { paper.place(params).await })
+    }
+
+    pub fn cancel_order(
+        &self,
+        symbol: &str,
+        order_id: u64,
+    ) -> Fallible<impl Future<Output = Fallible<OrderCanceled>>> {
+        let mut paper = self.lock();
+        let canceled = paper
+            .exchange
+            .cancel(&symbol_params(symbol, Some(order_id)));
+        paper.flush();
+        drop(paper);
+        Ok(future::ready(canceled.map_err(rejected).and_then(parse)))
+    }
+
+    pub fn trade_history(
+        &self,
+        symbol: &str,
+    ) -> Fallible<impl Future<Output = Fallible<Vec<TradeHistory>>>> {
+        let trades = self.lock().exchange.trades(&symbol_params(symbol, None));
+        Ok(future::ready(trades.map_err(rejected).and_then(parse)))
+    }
+
+    // Events of the simulated account, as `Binance::user_data_stream` yields
+    // them for a real one. The kind is accepted for symmetry only.
+    #[allow(clippy::unused_async)]
+    pub async fn user_data_stream(&self, _kind: UserStreamKind) -> Fallible<PaperUserStream> {
+        let mut paper = self.lock();
+        let listen_key = paper.exchange.create_listen_key()["listenKey"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let (tx, rx) = unbounded();
+        paper.streams.insert(listen_key.clone(), tx);
+        drop(paper);
+
+        Ok(PaperUserStream {
+            listen_key,
+            state: Arc::downgrade(&self.state),
+            rx,
+        })
+    }
+}
+
+// Market data is the exchange's own
+#[async_trait]
+impl MarketData for PaperBinance {
+    async fn ping(&self) -> Fallible<String> {
+        MarketData::ping(&self.binance).await
+    }
+
+    async fn get_server_time(&self) -> Fallible<ServerTime> {
+        MarketData::get_server_time(&self.binance).await
+    }
+
+    async fn get_exchange_info(&self) -> Fallible<ExchangeInfo> {
+        MarketData::get_exchange_info(&self.binance).await
+    }
+
+    async fn exchange_info(&self) -> Fallible<ExchangeInformation> {
+        MarketData::exchange_info(&self.binance).await
+    }
+
+    async fn get_depth(&self, symbol: &str, limit: Option<u64>) -> Fallible<OrderBook> {
+        MarketData::get_depth(&self.binance, symbol, limit).await
+    }
+
+    async fn get_all_prices(&self) -> Fallible<Prices> {
+        MarketData::get_all_prices(&self.binance).await
+    }
+
+    async fn get_price(&self, symbol: &str) -> Fallible<f64> {
+        MarketData::get_price(&self.binance, symbol).await
+    }
+
+    async fn get_all_book_tickers(&self) -> Fallible<BookTickers> {
+        MarketData::get_all_book_tickers(&self.binance).await
+    }
+
+    async fn get_book_ticker(&self, symbol: &str) -> Fallible<Ticker> {
+        MarketData::get_book_ticker(&self.binance, symbol).await
+    }
+
+    async fn get_24h_price_stats(&self, symbol: &str) -> Fallible<PriceStats> {
+        MarketData::get_24h_price_stats(&self.binance, symbol).await
+    }
+
+    async fn get_24h_price_stats_all(&self) -> Fallible<Vec<PriceStats>> {
+        MarketData::get_24h_price_stats_all(&self.binance).await
+    }
+
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Fallible<KlineSummaries> {
+        MarketData::get_klines(&self.binance, symbol, interval, limit, start_time, end_time).await
+    }
+}
+
+#[async_trait]
+impl Trading for PaperBinance {
+    async fn get_account(&self) -> Fallible<AccountInformation> {
+        Self::get_account(self)?.await
+    }
+
+    async fn get_balance(&self, asset: &str) -> Fallible<Balance> {
+        Self::get_balance(self, asset)?.await
+    }
+
+    async fn get_open_orders(&self, symbol: &str) -> Fallible<Vec<Order>> {
+        Self::get_open_orders(self, symbol)?.await
+    }
+
+    async fn get_all_open_orders(&self) -> Fallible<Vec<Order>> {
+        Self::get_all_open_orders(self)?.await
+    }
+
+    async fn order_status(&self, symbol: &str, order_id: u64) -> Fallible<Order> {
+        Self::order_status(self, symbol, order_id)?.await
+    }
+
+    async fn limit_buy(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        Self::limit_buy(self, symbol, qty, price)?.await
+    }
+
+    async fn limit_sell(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        Self::limit_sell(self, symbol, qty, price)?.await
+    }
+
+    async fn market_buy(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        Self::market_buy(self, symbol, qty)?.await
+    }
+
+    async fn market_sell(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        Self::market_sell(self, symbol, qty)?.await
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Fallible<OrderCanceled> {
+        Self::cancel_order(self, symbol, order_id)?.await
+    }
+
+    async fn trade_history(&self, symbol: &str) -> Fallible<Vec<TradeHistory>> {
+        Self::trade_history(self, symbol)?.await
+    }
+}
+
+enum Update {
+    Quote(BookTickerUpdate),
+    Trade(TradeMessage),
+}
+
+// Applies market data to the simulated exchange until the `PaperBinance` is gone
+async fn feed(
+    state: Weak<Mutex<Paper>>,
+    quotes: TypedStream<BookTickerUpdate>,
+    trades: TypedStream<TradeMessage>,
+) {
+    let mut updates = stream::select(quotes.map_ok(Update::Quote), trades.map_ok(Update::Trade));
+    while let Some(update) = updates.next().await {
+        let Some(state) = state.upgrade() else { break };
+        let mut paper = state.lock().unwrap_or_else(PoisonError::into_inner);
+        match update {
+            Ok(Update::Quote(quote)) => {
+                paper
+                    .exchange
+                    .quote(&quote.symbol, quote.best_bid, quote.best_ask);
+            }
+            Ok(Update::Trade(trade)) => paper.exchange.trade(&trade.symbol, trade.price),
+            Err(e) => warn!("[Paper] Market data error: {}", e),
+        }
+        paper.flush();
+    }
+}
+
+// User data events of a `PaperBinance`
+pub struct PaperUserStream {
+    listen_key: String,
+    state: Weak<Mutex<Paper>>,
+    rx: UnboundedReceiver<Fallible<UserDataEvent>>,
+}
+
+impl PaperUserStream {
+    #[must_use]
+    pub fn listen_key(&self) -> &str {
+        &self.listen_key
+    }
+}
+
+impl Stream for PaperUserStream {
+    type Item = Fallible<UserDataEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for PaperUserStream {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.upgrade() {
+            let mut paper = state.lock().unwrap_or_else(PoisonError::into_inner);
+            paper.exchange.listen_keys.remove(&self.listen_key);
+            paper.streams.remove(&self.listen_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PaperBinance;
+    use crate::{
+        client::{traits::Trading, userstream::UserStreamKind, Binance},
+        model::{websocket::UserDataEvent, AccountInformation, Balance},
+    };
+    use failure::Fallible;
+    use futures::{executor::block_on, prelude::*};
+
+    fn account() -> AccountInformation {
+        AccountInformation {
+            maker_commission: 10.0,
+            taker_commission: 10.0,
+            buyer_commission: 0.0,
+            seller_commission: 0.0,
+            can_trade: true,
+            can_withdraw: true,
+            can_deposit: true,
+            balances: vec![Balance {
+                asset: "BTC".to_string(),
+                free: "1.00000000".to_string(),
+                locked: "0.00000000".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn fills_on_quotes() -> Fallible<()> {
+        block_on(async {
+            let paper = PaperBinance::new(Binance::new(), &account())?;
+            paper.add_market("BNBBTC", "BNB", "BTC", 0.0019, 0.0021);
+            let mut events = paper.user_data_stream(UserStreamKind::Spot).await?;
+
+            // Takes the ask and pays the taker commission in BNB
+            paper.market_buy("BNBBTC", 10.0)?.await?;
+            assert_eq!(paper.get_balance("BNB")?.await?.free, "9.99000000");
+            assert_eq!(paper.get_balance("BTC")?.await?.free, "0.97900000");
+
+            let order = paper.limit_buy("BNBBTC", 100.0, 0.001)?.await?;
+            assert_eq!(paper.get_open_orders("BNBBTC")?.await?.len(), 1);
+            paper.quote("BNBBTC", 0.0009, 0.001);
+            let status = paper.order_status("BNBBTC", order.order_id)?.await?;
+            assert_eq!(status.status, "FILLED");
+
+            let trades = paper.trade_history("BNBBTC")?.await?;
+            assert_eq!(
+                trades.iter().map(|t| t.is_maker).collect::<Vec<_>>(),
+                vec![false, true]
+            );
+            assert!((trades[1].commission - 0.1).abs() < 1e-9);
+
+            let kinds: Vec<_> = events
+                .by_ref()
+                .take(6)
+                .map_ok(|event| match event {
+                    UserDataEvent::ExecutionReport(update) => {
+                        format!("{:?}", update.execution_type)
+                    }
+                    UserDataEvent::AccountPosition(_) => "Position".to_string(),
+                    other => format!("{:?}", other),
+                })
+                .try_collect()
+                .await?;
+            assert_eq!(
+                kinds,
+                vec!["New", "Trade", "Position", "New", "Trade", "Position"]
+            );
+            Ok(())
+        })
+    }
+
+    // Strategy code written against the trait runs on paper unchanged
+    async fn buy_dip(trading: &dyn Trading, symbol: &str, price: f64) -> Fallible<u64> {
+        let order = trading.limit_buy(symbol, 10.0, price).await?;
+        Ok(order.order_id)
+    }
+
+    #[test]
+    fn as_trading() -> Fallible<()> {
+        block_on(async {
+            let paper = PaperBinance::new(Binance::new(), &account())?;
+            paper.add_market("BNBBTC", "BNB", "BTC", 0.0019, 0.0021);
+
+            let order_id = buy_dip(&paper, "BNBBTC", 0.0015).await?;
+            paper.trade("BNBBTC", 0.0015);
+            let order = Trading::order_status(&paper, "BNBBTC", order_id).await?;
+            assert_eq!(order.status, "FILLED");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn rejections() -> Fallible<()> {
+        block_on(async {
+            let paper = PaperBinance::new(Binance::new(), &account())?;
+            paper.add_market("BNBBTC", "BNB", "BTC", 0.0019, 0.0021);
+
+            let e = paper.market_sell("BNBBTC", 1.0)?.await.unwrap_err();
+            assert!(e.to_string().contains("insufficient balance"));
+            assert!(paper.cancel_order("BNBBTC", 42)?.await.is_err());
+            assert!(paper.get_balance("ETH")?.await.is_err());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_balance() {
+        let mut account = account();
+        account.balances[0].free = "1,5".to_string();
+        assert!(PaperBinance::new(Binance::new(), &account).is_err());
+    }
+}
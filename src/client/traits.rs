@@ -0,0 +1,193 @@
+// REST endpoints as object-safe traits, so strategy code can take
+// `&dyn Trading` and run against `Binance`, `PaperBinance` or a test double.
+// Implementations can use `#[async_trait]` too.
+
+use crate::{
+    client::Binance,
+    model::{
+        AccountInformation, AssetDetail, Balance, BookTickers, DepositAddressData, DepositHistory,
+        ExchangeInfo, ExchangeInformation, KlineSummaries, Order, OrderBook, OrderCanceled,
+        PriceStats, Prices, ServerTime, Ticker, TradeHistory, Transaction,
+    },
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use failure::Fallible;
+
+// Public endpoints of `client/general.rs` and `client/market.rs`
+#[async_trait]
+pub trait MarketData: Send + Sync {
+    async fn ping(&self) -> Fallible<String>;
+    async fn get_server_time(&self) -> Fallible<ServerTime>;
+    async fn get_exchange_info(&self) -> Fallible<ExchangeInfo>;
+    async fn exchange_info(&self) -> Fallible<ExchangeInformation>;
+    async fn get_depth(&self, symbol: &str, limit: Option<u64>) -> Fallible<OrderBook>;
+    async fn get_all_prices(&self) -> Fallible<Prices>;
+    async fn get_price(&self, symbol: &str) -> Fallible<f64>;
+    async fn get_all_book_tickers(&self) -> Fallible<BookTickers>;
+    async fn get_book_ticker(&self, symbol: &str) -> Fallible<Ticker>;
+    async fn get_24h_price_stats(&self, symbol: &str) -> Fallible<PriceStats>;
+    async fn get_24h_price_stats_all(&self) -> Fallible<Vec<PriceStats>>;
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Fallible<KlineSummaries>;
+}
+
+// Account and order endpoints of `client/account.rs`
+#[async_trait]
+pub trait Trading: Send + Sync {
+    async fn get_account(&self) -> Fallible<AccountInformation>;
+    async fn get_balance(&self, asset: &str) -> Fallible<Balance>;
+    async fn get_open_orders(&self, symbol: &str) -> Fallible<Vec<Order>>;
+    async fn get_all_open_orders(&self) -> Fallible<Vec<Order>>;
+    async fn order_status(&self, symbol: &str, order_id: u64) -> Fallible<Order>;
+    async fn limit_buy(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction>;
+    async fn limit_sell(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction>;
+    async fn market_buy(&self, symbol: &str, qty: f64) -> Fallible<Transaction>;
+    async fn market_sell(&self, symbol: &str, qty: f64) -> Fallible<Transaction>;
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Fallible<OrderCanceled>;
+    async fn trade_history(&self, symbol: &str) -> Fallible<Vec<TradeHistory>>;
+}
+
+// Deposit endpoints of `client/account.rs`
+#[async_trait]
+pub trait Wallet: Send + Sync {
+    async fn get_deposit_address(&self, asset: &str) -> Fallible<DepositAddressData>;
+    async fn get_deposit_history(
+        &self,
+        asset: Option<&str>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Fallible<DepositHistory>;
+    async fn asset_detail(&self) -> Fallible<AssetDetail>;
+}
+
+#[async_trait]
+impl MarketData for Binance {
+    async fn ping(&self) -> Fallible<String> {
+        Self::ping(self)?.await
+    }
+
+    async fn get_server_time(&self) -> Fallible<ServerTime> {
+        Self::get_server_time(self)?.await
+    }
+
+    async fn get_exchange_info(&self) -> Fallible<ExchangeInfo> {
+        Self::get_exchange_info(self)?.await
+    }
+
+    async fn exchange_info(&self) -> Fallible<ExchangeInformation> {
+        Self::exchange_info(self)?.await
+    }
+
+    async fn get_depth(&self, symbol: &str, limit: Option<u64>) -> Fallible<OrderBook> {
+        Self::get_depth(self, symbol, limit)?.await
+    }
+
+    async fn get_all_prices(&self) -> Fallible<Prices> {
+        Self::get_all_prices(self)?.await
+    }
+
+    async fn get_price(&self, symbol: &str) -> Fallible<f64> {
+        Self::get_price(self, symbol)?.await
+    }
+
+    async fn get_all_book_tickers(&self) -> Fallible<BookTickers> {
+        Self::get_all_book_tickers(self)?.await
+    }
+
+    async fn get_book_ticker(&self, symbol: &str) -> Fallible<Ticker> {
+        Self::get_book_ticker(self, symbol)?.await
+    }
+
+    async fn get_24h_price_stats(&self, symbol: &str) -> Fallible<PriceStats> {
+        Self::get_24h_price_stats(self, symbol)?.await
+    }
+
+    async fn get_24h_price_stats_all(&self) -> Fallible<Vec<PriceStats>> {
+        Self::get_24h_price_stats_all(self)?.await
+    }
+
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> Fallible<KlineSummaries> {
+        Self::get_klines(self, symbol, interval, limit, start_time, end_time)?.await
+    }
+}
+
+#[async_trait]
+impl Trading for Binance {
+    async fn get_account(&self) -> Fallible<AccountInformation> {
+        Self::get_account(self)?.await
+    }
+
+    async fn get_balance(&self, asset: &str) -> Fallible<Balance> {
+        Self::get_balance(self, asset)?.await
+    }
+
+    async fn get_open_orders(&self, symbol: &str) -> Fallible<Vec<Order>> {
+        Self::get_open_orders(self, symbol)?.await
+    }
+
+    async fn get_all_open_orders(&self) -> Fallible<Vec<Order>> {
+        Self::get_all_open_orders(self)?.await
+    }
+
+    async fn order_status(&self, symbol: &str, order_id: u64) -> Fallible<Order> {
+        Self::order_status(self, symbol, order_id)?.await
+    }
+
+    async fn limit_buy(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        Self::limit_buy(self, symbol, qty, price)?.await
+    }
+
+    async fn limit_sell(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        Self::limit_sell(self, symbol, qty, price)?.await
+    }
+
+    async fn market_buy(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        Self::market_buy(self, symbol, qty)?.await
+    }
+
+    async fn market_sell(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        Self::market_sell(self, symbol, qty)?.await
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Fallible<OrderCanceled> {
+        Self::cancel_order(self, symbol, order_id)?.await
+    }
+
+    async fn trade_history(&self, symbol: &str) -> Fallible<Vec<TradeHistory>> {
+        Self::trade_history(self, symbol)?.await
+    }
+}
+
+#[async_trait]
+impl Wallet for Binance {
+    async fn get_deposit_address(&self, asset: &str) -> Fallible<DepositAddressData> {
+        Self::get_deposit_address(self, asset)?.await
+    }
+
+    async fn get_deposit_history(
+        &self,
+        asset: Option<&str>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Fallible<DepositHistory> {
+        Self::get_deposit_history(self, asset, start_time, end_time)?.await
+    }
+
+    async fn asset_detail(&self) -> Fallible<AssetDetail> {
+        Self::asset_detail(self)?.await
+    }
+}
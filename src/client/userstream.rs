@@ -1,39 +1,333 @@
 use crate::{
-    client::Binance,
-    model::{Success, UserDataStream},
+    client::{websocket::BinanceWebsocket, Binance},
+    error::Error,
+    model::{
+        self,
+        websocket::{BinanceWebsocketMessage, Subscription, UserDataEvent},
+        Success,
+    },
 };
 use failure::Fallible;
-use futures::prelude::*;
+use futures::{
+    channel::oneshot,
+    future::{self, AbortHandle},
+    prelude::*,
+};
+use std::{
+    collections::HashMap,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{runtime::Handle, time::delay_for};
+use tracing::*;
+
+// Listen keys expire after 60 minutes without a keepalive
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+// Account a listen key is issued for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UserStreamKind {
+    Spot,
+    Margin,
+    IsolatedMargin(String), // symbol
+}
 
-const USER_DATA_STREAM: &str = "/api/v1/userDataStream";
+impl UserStreamKind {
+    const fn endpoint(&self) -> &'static str {
+        match self {
+            Self::Spot => "/api/v3/userDataStream",
+            Self::Margin => "/sapi/v1/userDataStream",
+            Self::IsolatedMargin(_) => "/sapi/v1/userDataStream/isolated",
+        }
+    }
+
+    fn params(&self, listen_key: Option<&str>) -> HashMap<&'static str, String> {
+        let mut params = HashMap::new();
+        if let Self::IsolatedMargin(symbol) = self {
+            params.insert("symbol", symbol.to_uppercase());
+        }
+        if let Some(listen_key) = listen_key {
+            params.insert("listenKey", listen_key.to_string());
+        }
+        params
+    }
+}
 
 impl Binance {
     // User Stream
-    pub fn user_stream_start(&self) -> Fallible<impl Future<Output = Fallible<UserDataStream>>> {
-        let user_data_stream = self.transport.post::<_, ()>(USER_DATA_STREAM, None)?;
+    pub fn user_stream_start(
+        &self,
+        kind: &UserStreamKind,
+    ) -> Fallible<impl Future<Output = Fallible<model::UserDataStream>>> {
+        let user_data_stream = self
+            .transport
+            .post(kind.endpoint(), Some(kind.params(None)))?;
         Ok(user_data_stream)
     }
 
-    // Current open orders on a symbol
+    // Extends the listen key validity by 60 minutes
     pub fn user_stream_keep_alive(
         &self,
+        kind: &UserStreamKind,
         listen_key: &str,
     ) -> Fallible<impl Future<Output = Fallible<Success>>> {
-        let success = self.transport.put(
-            USER_DATA_STREAM,
-            Some(vec![("listen_key", listen_key.to_string())]),
-        )?;
+        let success = self
+            .transport
+            .put(kind.endpoint(), Some(kind.params(Some(listen_key))))?;
         Ok(success)
     }
 
     pub fn user_stream_close(
         &self,
+        kind: &UserStreamKind,
         listen_key: &str,
     ) -> Fallible<impl Future<Output = Fallible<Success>>> {
-        let success = self.transport.delete(
-            USER_DATA_STREAM,
-            Some(vec![("listen_key", listen_key.to_string())]),
-        )?;
+        let success = self
+            .transport
+            .delete(kind.endpoint(), Some(kind.params(Some(listen_key))))?;
         Ok(success)
     }
+
+    // User data events over a listen key that is kept alive and renewed automatically
+    pub async fn user_data_stream(&self, kind: UserStreamKind) -> Fallible<UserDataStream> {
+        let (listen_key, websocket) =
+            open_stream(self.clone(), kind.clone(), Duration::from_secs(0)).await?;
+
+        Ok(UserDataStream {
+            state: StreamState::Open(Box::new(Session::new(
+                self.clone(),
+                kind.clone(),
+                listen_key,
+                websocket,
+            ))),
+            binance: self.clone(),
+            kind,
+        })
+    }
+}
+
+type RestartFuture = Pin<Box<dyn Future<Output = Fallible<(String, BinanceWebsocket)>> + Send>>;
+
+async fn open_stream(
+    binance: Binance,
+    kind: UserStreamKind,
+    delay: Duration,
+) -> Fallible<(String, BinanceWebsocket)> {
+    if delay > Duration::from_secs(0) {
+        delay_for(delay).await;
+    }
+
+    let listen_key = binance.user_stream_start(&kind)?.await?.listen_key;
+    let mut websocket = BinanceWebsocket::with_config(binance.transport.config().clone());
+    websocket
+        .subscribe(Subscription::UserData(listen_key.clone()))
+        .await?;
+
+    Ok((listen_key, websocket))
+}
+
+async fn keep_alive(binance: &Binance, kind: &UserStreamKind, listen_key: &str) -> Fallible<()> {
+    binance.user_stream_keep_alive(kind, listen_key)?.await?;
+    Ok(())
+}
+
+async fn close(binance: Binance, kind: UserStreamKind, listen_key: String) {
+    let result = match binance.user_stream_close(&kind, &listen_key) {
+        Ok(closed) => closed.await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("[UserStream] Failed to close listen key: {}", e);
+    }
+}
+
+struct Session {
+    kind: UserStreamKind,
+    listen_key: String,
+    websocket: BinanceWebsocket,
+    keepalive: AbortHandle,
+    keepalive_failed: oneshot::Receiver<()>,
+}
+
+impl Session {
+    // Spawns the keepalive timer for the listen key
+    fn new(
+        binance: Binance,
+        kind: UserStreamKind,
+        listen_key: String,
+        websocket: BinanceWebsocket,
+    ) -> Self {
+        let (failed, keepalive_failed) = oneshot::channel();
+        let (stream_kind, key) = (kind.clone(), listen_key.clone());
+        let (task, keepalive) = future::abortable(async move {
+            loop {
+                delay_for(KEEPALIVE_INTERVAL).await;
+                if let Err(e) = keep_alive(&binance, &stream_kind, &key).await {
+                    warn!("[UserStream] Listen key keepalive failed: {}", e);
+                    let _ = failed.send(());
+                    return;
+                }
+                trace!("[UserStream] Listen key extended");
+            }
+        });
+        tokio::spawn(task);
+
+        Self {
+            kind,
+            listen_key,
+            websocket,
+            keepalive,
+            keepalive_failed,
+        }
+    }
+
+    // Stops the keepalive and closes the listen key in the background
+    fn close(self, binance: &Binance) {
+        self.keepalive.abort();
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(close(binance.clone(), self.kind, self.listen_key));
+        } else {
+            warn!("[UserStream] No runtime to close the listen key on");
+        }
+    }
+}
+
+enum StreamState {
+    Open(Box<Session>),
+    Restarting(RestartFuture),
+}
+
+// Continuous stream of user data events. The listen key is extended every
+// 30 minutes, recreated when it expires and closed when the stream is dropped.
+// Reconnects surface as `StreamDisconnected`/`StreamReconnected` errors, events
+// may have been missed in between and are best reconciled over REST.
+pub struct UserDataStream {
+    binance: Binance,
+    kind: UserStreamKind,
+    state: StreamState,
+}
+
+impl UserDataStream {
+    #[must_use]
+    pub const fn kind(&self) -> &UserStreamKind {
+        &self.kind
+    }
+
+    #[must_use]
+    pub fn listen_key(&self) -> Option<&str> {
+        match &self.state {
+            StreamState::Open(session) => Some(&session.listen_key),
+            StreamState::Restarting(_) => None,
+        }
+    }
+
+    fn restart(&mut self, delay: Duration) {
+        debug!("[UserStream] Recreating listen key");
+        let restart = Box::pin(open_stream(self.binance.clone(), self.kind.clone(), delay));
+        if let StreamState::Open(session) =
+            mem::replace(&mut self.state, StreamState::Restarting(restart))
+        {
+            session.close(&self.binance);
+        }
+    }
+}
+
+impl Stream for UserDataStream {
+    type Item = Fallible<UserDataEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            match &mut this.state {
+                StreamState::Restarting(restart) => match restart.as_mut().poll(cx) {
+                    Poll::Ready(Ok((listen_key, websocket))) => {
+                        let session = Session::new(
+                            this.binance.clone(),
+                            this.kind.clone(),
+                            listen_key,
+                            websocket,
+                        );
+                        this.state = StreamState::Open(Box::new(session));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        this.restart(RESTART_DELAY);
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                StreamState::Open(session) => {
+                    if Pin::new(&mut session.keepalive_failed).poll(cx) == Poll::Ready(Ok(())) {
+                        this.restart(Duration::from_secs(0));
+                        continue;
+                    }
+
+                    match Pin::new(&mut session.websocket).poll_next(cx) {
+                        Poll::Ready(Some(Ok(BinanceWebsocketMessage::UserData(event)))) => {
+                            if let UserDataEvent::ListenKeyExpired(_) = event {
+                                this.restart(Duration::from_secs(0));
+                            }
+                            return Poll::Ready(Some(Ok(event)));
+                        }
+                        // The websocket reconnects on its own and the listen key stays
+                        // valid, but events sent meanwhile are lost
+                        Poll::Ready(Some(Ok(BinanceWebsocketMessage::Disconnected(_)))) => {
+                            return Poll::Ready(Some(Err(Error::StreamDisconnected.into())))
+                        }
+                        Poll::Ready(Some(Ok(BinanceWebsocketMessage::Reconnected(_)))) => {
+                            return Poll::Ready(Some(Err(Error::StreamReconnected.into())))
+                        }
+                        Poll::Ready(Some(Ok(_))) => {}
+                        Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                        Poll::Ready(None) => this.restart(Duration::from_secs(0)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UserDataStream {
+    fn drop(&mut self) {
+        let restart = StreamState::Restarting(Box::pin(future::pending()));
+        if let StreamState::Open(session) = mem::replace(&mut self.state, restart) {
+            session.close(&self.binance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UserStreamKind;
+    use crate::{error::BinanceResponse, model::Success};
+    use failure::Fallible;
+    use serde_json::{from_value, json};
+
+    #[test]
+    fn listen_key_params() {
+        let spot = UserStreamKind::Spot.params(Some("key"));
+        assert_eq!(spot.get("listenKey").map(String::as_str), Some("key"));
+        assert_eq!(spot.len(), 1);
+
+        let isolated = UserStreamKind::IsolatedMargin("btcusdt".into());
+        assert_eq!(isolated.endpoint(), "/sapi/v1/userDataStream/isolated");
+        let params = isolated.params(None);
+        assert_eq!(params.get("symbol").map(String::as_str), Some("BTCUSDT"));
+        assert!(!params.contains_key("listenKey"));
+    }
+
+    #[test]
+    fn keepalive_errors() -> Fallible<()> {
+        let ok: BinanceResponse<Success> = from_value(json!({}))?;
+        assert!(ok.into_result().is_ok());
+
+        let err: BinanceResponse<Success> =
+            from_value(json!({"code": -1125, "msg": "This listenKey does not exist."}))?;
+        assert!(err.into_result().is_err());
+        Ok(())
+    }
 }
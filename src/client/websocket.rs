@@ -1,135 +1,989 @@
 use crate::{
+    config::ClientConfig,
     error::Error,
-    model::websocket::{AccountUpdate, BinanceWebsocketMessage, Subscription, UserOrderUpdate},
+    model::websocket::{BinanceWebsocketMessage, Subscription},
 };
+use chrono::Utc;
 use failure::Fallible;
-use futures::{prelude::*, stream::SplitStream};
-use serde::{Deserialize, Serialize};
-use serde_json::from_str;
+use futures::prelude::*;
+use serde::Deserialize;
+use serde_json::{from_str, from_value, json, Value};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, VecDeque},
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
-use streamunordered::{StreamUnordered, StreamYield};
-use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio::{
+    net::TcpStream,
+    time::{delay_for, delay_until, Delay, Instant},
+};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use tracing::*;
 use tungstenite::Message;
 use url::Url;
 
-const WS_URL: &str = "wss://stream.binance.com:9443/ws";
+const WS_URL: &str = "wss://stream.binance.com:9443/stream";
+// Binance limit of streams on a single combined connection
+const MAX_STREAMS_PER_CONNECTION: usize = 1024;
+// Binance limit of incoming control messages per connection
+const MAX_MESSAGES_PER_SECOND: usize = 5;
+// Binance drops connections after 24 hours, rotate them slightly earlier
+const MAX_CONNECTION_AGE: Duration = Duration::from_secs(23 * 60 * 60 + 50 * 60);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Stream messages kept while waiting for a method response, oldest dropped first
+const MAX_BUFFERED: usize = 4096;
 
-#[allow(dead_code)]
 type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type ConnectFuture = Pin<Box<dyn Future<Output = Fallible<WSStream>> + Send>>;
+// Message along with the subscription it arrived on, if any
+pub type Routed = (Option<Subscription>, Fallible<BinanceWebsocketMessage>);
+
+#[allow(clippy::large_enum_variant)]
+enum Socket {
+    Open(WSStream),
+    Reconnecting(ConnectFuture),
+}
+
+struct Connection {
+    id: usize,
+    config: Arc<ClientConfig>,
+    streams: BTreeSet<String>,
+    socket: Socket,
+    connected_at: Instant,
+    attempts: u32,
+    sent: VecDeque<Instant>,
+    pongs: VecDeque<Vec<u8>>,
+}
+
+fn endpoint(config: &ClientConfig, streams: &BTreeSet<String>) -> Url {
+    let mut endpoint = config
+        .stream_url
+        .clone()
+        .unwrap_or_else(|| Url::parse(WS_URL).unwrap());
+    endpoint.query_pairs_mut().append_pair(
+        "streams",
+        &streams.iter().cloned().collect::<Vec<_>>().join("/"),
+    );
+    endpoint
+}
+
+fn connect(config: Arc<ClientConfig>, endpoint: Url, delay: Duration) -> ConnectFuture {
+    Box::pin(async move {
+        if delay > Duration::from_secs(0) {
+            delay_for(delay).await;
+        }
+        trace!(
+            "[Websocket] Connecting combined stream '{}'",
+            log_endpoint(&endpoint)
+        );
+        config.connect_websocket(endpoint).await
+    })
+}
+
+// Stream name fit for logs. User data streams are named after the listen key,
+// every market stream name has an '@' or starts with '!'.
+fn log_name(name: &str) -> &str {
+    if name.contains(['@', '!']) {
+        name
+    } else {
+        "userData"
+    }
+}
+
+fn log_names<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    names
+        .into_iter()
+        .map(log_name)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn log_endpoint(endpoint: &Url) -> Url {
+    let mut redacted = endpoint.clone();
+    redacted
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(endpoint.query_pairs().map(|(key, value)| {
+            let value = match &*key {
+                "streams" => log_names(value.split('/')),
+                _ => value.into_owned(),
+            };
+            (key, value)
+        }));
+    redacted
+}
+
+// Reconnect immediately at first, then back off exponentially
+fn backoff(attempts: u32) -> Duration {
+    match attempts {
+        0 => Duration::from_secs(0),
+        n => MAX_BACKOFF.min(Duration::from_secs(1 << (n - 1).min(6))),
+    }
+}
+
+impl Connection {
+    async fn open(
+        id: usize,
+        config: Arc<ClientConfig>,
+        streams: BTreeSet<String>,
+    ) -> Fallible<Self> {
+        let socket = connect(
+            config.clone(),
+            endpoint(&config, &streams),
+            Duration::from_secs(0),
+        )
+        .await?;
+
+        Ok(Self {
+            id,
+            config,
+            streams,
+            socket: Socket::Open(socket),
+            connected_at: Instant::now(),
+            attempts: 0,
+            sent: VecDeque::new(),
+            pongs: VecDeque::new(),
+        })
+    }
+
+    const fn is_open(&self) -> bool {
+        matches!(self.socket, Socket::Open(_))
+    }
+
+    fn reconnect(&mut self) {
+        #[cfg(feature = "metrics")]
+        crate::telemetry::reconnect();
+        let delay = backoff(self.attempts);
+        self.attempts += 1;
+        self.sent.clear();
+        self.pongs.clear();
+        self.socket = Socket::Reconnecting(connect(
+            self.config.clone(),
+            endpoint(&self.config, &self.streams),
+            delay,
+        ));
+    }
+
+    // Waits until another control message fits in the rate limit
+    async fn throttle(&mut self) {
+        let window = Duration::from_secs(1);
+        while self.sent.len() >= MAX_MESSAGES_PER_SECOND {
+            let oldest = self.sent[0];
+            if oldest.elapsed() < window {
+                delay_until(oldest + window).await;
+            }
+            self.sent.pop_front();
+        }
+        self.sent.push_back(Instant::now());
+    }
+}
+
+// Writes queued pong replies and flushes the socket
+fn poll_pongs(
+    socket: &mut WSStream,
+    pongs: &mut VecDeque<Vec<u8>>,
+    cx: &mut Context<'_>,
+) -> Result<(), tungstenite::Error> {
+    while let Some(payload) = pongs.pop_front() {
+        match Pin::new(&mut *socket).poll_ready(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut *socket).start_send(Message::Pong(payload))?,
+            Poll::Ready(Err(e)) => return Err(e),
+            Poll::Pending => {
+                pongs.push_front(payload);
+                return Ok(());
+            }
+        }
+    }
 
-pub type StoredStream = SplitStream<WSStream>;
+    match Pin::new(socket).poll_flush(cx) {
+        Poll::Ready(Err(e)) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    pub messages: u64,
+    pub last_received: std::time::Instant,
+    // Local receive time minus the event time of the last message
+    pub latency_ms: Option<i64>,
+    // Nothing received within the idle timeout
+    pub stale: bool,
+}
+
+impl StreamStats {
+    fn new() -> Self {
+        Self {
+            messages: 0,
+            last_received: std::time::Instant::now(),
+            latency_ms: None,
+            stale: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum IncomingMessage {
+    Stream { stream: String, data: Value },
+    Error { id: u64, code: i64, msg: String },
+    Response { id: u64, result: Value },
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Incoming {
+    Message(Option<Subscription>, BinanceWebsocketMessage),
+    Response(u64, Fallible<Value>),
+    Closed,
+    Ignored,
+}
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Default)]
 pub struct BinanceWebsocket {
-    subscriptions: HashMap<Subscription, usize>,
-    tokens: HashMap<usize, Subscription>,
-    streams: StreamUnordered<StoredStream>,
+    subscriptions: HashMap<String, Subscription>,
+    connections: Vec<Connection>,
+    next_poll: usize,
+    next_connection_id: usize,
+    next_request_id: u64,
+    // Request id -> connection id of requests awaiting a response
+    requests: HashMap<u64, usize>,
+    responses: HashMap<u64, Fallible<Value>>,
+    // Stream messages read while waiting for a response
+    buffer: VecDeque<Routed>,
+    buffer_dropped: u64,
+    stats: HashMap<String, StreamStats>,
+    idle_timeouts: HashMap<String, Duration>,
+    idle_timer: Option<Delay>,
+    config: Arc<ClientConfig>,
 }
 
 impl BinanceWebsocket {
+    // Connects through the proxy and with the connect timeout of the config
+    #[must_use]
+    pub fn with_config(config: ClientConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            ..Self::default()
+        }
+    }
+
     pub async fn subscribe(&mut self, subscription: Subscription) -> Fallible<()> {
-        let sub = match subscription {
-            Subscription::AggregateTrade(ref symbol) => format!("{}@aggTrade", symbol),
-            Subscription::Candlestick(ref symbol, ref interval) => {
-                format!("{}@kline_{}", symbol, interval)
-            }
-            Subscription::Depth(ref symbol) => format!("{}@depth", symbol),
-            Subscription::MiniTicker(ref symbol) => format!("{}@miniTicker", symbol),
-            Subscription::MiniTickerAll => "!miniTicker@arr".to_string(),
-            Subscription::OrderBook(ref symbol, depth) => format!("{}@depth{}", symbol, depth),
-            Subscription::Ticker(ref symbol) => format!("{}@ticker", symbol),
-            Subscription::TickerAll => "!ticker@arr".to_string(),
-            Subscription::Trade(ref symbol) => format!("{}@trade", symbol),
-            Subscription::UserData(ref key) => key.clone(),
+        self.subscribe_many(std::iter::once(subscription)).await
+    }
+
+    // Subscribes to several streams at once, sharding them over combined connections
+    pub async fn subscribe_many<I>(&mut self, subscriptions: I) -> Fallible<()>
+    where
+        I: IntoIterator<Item = Subscription>,
+    {
+        let mut pending = Vec::new();
+        for subscription in subscriptions {
+            let name = subscription.stream_name();
+            if !self.subscriptions.contains_key(&name) && pending.iter().all(|(n, _)| *n != name) {
+                pending.push((name, subscription));
+            }
+        }
+
+        let span = info_span!(
+            "binance_subscribe",
+            streams = %pending
+                .iter()
+                .map(|(name, _)| log_name(name))
+                .collect::<Vec<_>>()
+                .join(","),
+            count = pending.len(),
+        );
+        self.open_streams(pending).instrument(span).await
+    }
+
+    async fn open_streams(&mut self, mut pending: Vec<(String, Subscription)>) -> Fallible<()> {
+        while !pending.is_empty() {
+            // Top up a connection with spare room, otherwise open a new one
+            let slot = self
+                .connections
+                .iter()
+                .find(|c| c.is_open() && c.streams.len() < MAX_STREAMS_PER_CONNECTION)
+                .map(|c| (c.id, MAX_STREAMS_PER_CONNECTION - c.streams.len()));
+
+            let room = slot.map_or(MAX_STREAMS_PER_CONNECTION, |(_, room)| room);
+            let batch: Vec<_> = pending.drain(..room.min(pending.len())).collect();
+            let names: Vec<_> = batch.iter().map(|(name, _)| name.clone()).collect();
+
+            if let Some((id, _)) = slot {
+                self.request(id, "SUBSCRIBE", json!(names)).await?;
+                if let Some(connection) = self.connection_mut(id) {
+                    connection.streams.extend(names);
+                }
+            } else {
+                let id = self.next_connection_id;
+                self.next_connection_id += 1;
+                let connection =
+                    Connection::open(id, self.config.clone(), names.into_iter().collect()).await?;
+                self.connections.push(connection);
+            }
+
+            for (name, subscription) in batch {
+                trace!("[Websocket] Subscribed to '{}'", log_name(&name));
+                self.stats.insert(name.clone(), StreamStats::new());
+                self.subscriptions.insert(name, subscription);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Unsubscribes the stream live. Connections left without streams are closed.
+    pub async fn unsubscribe(&mut self, subscription: &Subscription) -> Fallible<bool> {
+        let name = subscription.stream_name();
+        let (id, last_stream) = match self.connections.iter().find(|c| c.streams.contains(&name)) {
+            Some(connection) => (connection.id, connection.streams.len() == 1),
+            None => return Ok(false),
         };
 
-        trace!("[Websocket] Subscribing to '{:?}'", subscription);
+        if last_stream {
+            let idx = self.connections.iter().position(|c| c.id == id);
+            if let Some(idx) = idx {
+                let connection = self.connections.remove(idx);
+                self.fail_requests(id);
+                if let Socket::Open(mut socket) = connection.socket {
+                    futures::SinkExt::close(&mut socket).await?;
+                }
+            }
+        } else {
+            self.request(id, "UNSUBSCRIBE", json!([name])).await?;
+            if let Some(connection) = self.connection_mut(id) {
+                connection.streams.remove(&name);
+            }
+        }
 
-        let endpoint = Url::parse(&format!("{}/{}", WS_URL, sub)).unwrap();
+        self.subscriptions.remove(&name);
+        self.stats.remove(&name);
+        self.idle_timeouts.remove(&name);
+        trace!("[Websocket] Unsubscribed from '{}'", log_name(&name));
 
-        let token = self
-            .streams
-            .push(connect_async(endpoint).await?.0.split().1);
+        Ok(true)
+    }
+
+    // Flags the subscription as stale when no data arrives within the timeout
+    pub fn set_idle_timeout(&mut self, subscription: &Subscription, timeout: Option<Duration>) {
+        let name = subscription.stream_name();
+        match timeout {
+            Some(timeout) => self.idle_timeouts.insert(name, timeout),
+            None => self.idle_timeouts.remove(&name),
+        };
+    }
+
+    #[must_use]
+    pub fn stats(&self, subscription: &Subscription) -> Option<&StreamStats> {
+        self.stats.get(&subscription.stream_name())
+    }
+
+    // Streams the server reports as subscribed, over all connections
+    pub async fn list_subscriptions(&mut self) -> Fallible<Vec<String>> {
+        let mut streams = Vec::new();
+        for id in self.connection_ids() {
+            let result = self.request(id, "LIST_SUBSCRIPTIONS", json!([])).await?;
+            streams.extend(from_value::<Vec<String>>(result)?);
+        }
+        Ok(streams)
+    }
 
-        self.subscriptions.insert(subscription.clone(), token);
-        self.tokens.insert(token, subscription);
+    pub async fn set_property(&mut self, property: &str, value: Value) -> Fallible<()> {
+        for id in self.connection_ids() {
+            self.request(id, "SET_PROPERTY", json!([property, value]))
+                .await?;
+        }
         Ok(())
     }
 
-    pub fn unsubscribe(&mut self, subscription: &Subscription) -> Option<StoredStream> {
-        let streams = Pin::new(&mut self.streams);
-        self.subscriptions
-            .get(subscription)
-            .and_then(|token| StreamUnordered::take(streams, *token))
+    pub async fn get_property(&mut self, property: &str) -> Fallible<Value> {
+        let id = self
+            .connection_ids()
+            .into_iter()
+            .next()
+            .ok_or(Error::NoStreamSubscribed)?;
+        self.request(id, "GET_PROPERTY", json!([property])).await
     }
-}
 
-impl Stream for BinanceWebsocket {
-    type Item = Fallible<BinanceWebsocketMessage>;
+    fn connection_ids(&self) -> Vec<usize> {
+        self.connections.iter().map(|c| c.id).collect()
+    }
+
+    fn connection_mut(&mut self, id: usize) -> Option<&mut Connection> {
+        self.connections.iter_mut().find(|c| c.id == id)
+    }
+
+    // Sends a method frame and waits for the matching response
+    async fn request(&mut self, connection: usize, method: &str, params: Value) -> Fallible<Value> {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+
+        let frame = json!({ "method": method, "params": params, "id": id });
+        trace!("[Websocket] Sending {} request {}", method, id);
+
+        let conn = self
+            .connection_mut(connection)
+            .ok_or(Error::NoStreamSubscribed)?;
+        conn.throttle().await;
+        match &mut conn.socket {
+            Socket::Open(socket) => socket.send(Message::Text(frame.to_string())).await?,
+            Socket::Reconnecting(_) => return Err(failure::format_err!("Socket closed")),
+        }
+        self.requests.insert(id, connection);
+
+        future::poll_fn(|cx| self.poll_response(id, cx)).await
+    }
+
+    fn poll_response(&mut self, id: u64, cx: &mut Context<'_>) -> Poll<Fallible<Value>> {
+        loop {
+            if let Some(response) = self.responses.remove(&id) {
+                if self.buffer_dropped > 0 {
+                    warn!(
+                        "[Websocket] Dropped {} stream messages while waiting for a response",
+                        self.buffer_dropped
+                    );
+                    self.buffer_dropped = 0;
+                }
+                return Poll::Ready(response);
+            }
+
+            match self.poll_connections(cx) {
+                Poll::Ready(Some(item)) => self.buffer_item(item),
+                // The response may have been read before the sockets ran dry
+                Poll::Ready(None) | Poll::Pending if !self.responses.contains_key(&id) => {
+                    return Poll::Pending
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+        }
+    }
+
+    fn buffer_item(&mut self, item: Routed) {
+        if self.buffer.len() >= MAX_BUFFERED {
+            self.buffer.pop_front();
+            self.buffer_dropped += 1;
+        }
+        self.buffer.push_back(item);
+    }
+
+    // Fails the requests still waiting for a response on the connection
+    fn fail_requests(&mut self, connection: usize) {
+        let orphaned: Vec<_> = self
+            .requests
+            .iter()
+            .filter(|(_, conn)| **conn == connection)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in orphaned {
+            self.requests.remove(&id);
+            self.responses
+                .insert(id, Err(failure::format_err!("Socket closed")));
+        }
+    }
+
+    fn connection_subscriptions(&self, idx: usize) -> Vec<Subscription> {
+        self.connections[idx]
+            .streams
+            .iter()
+            .filter_map(|name| self.subscriptions.get(name).cloned())
+            .collect()
+    }
+
+    fn disconnect(&mut self, idx: usize) -> BinanceWebsocketMessage {
+        let id = self.connections[idx].id;
+        self.fail_requests(id);
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.as_mut().get_mut().streams).poll_next(cx) {
-            Poll::Ready(Some((y, token))) => match y {
-                StreamYield::Item(item) => {
-                    let sub = self.tokens.get(&token).unwrap();
-                    Poll::Ready({
-                        Some(
-                            item.map_err(failure::Error::from)
-                                .and_then(|m| parse_message(sub, m)),
-                        )
-                    })
+        let connection = &mut self.connections[idx];
+        warn!(
+            "[Websocket] Connection lost, reconnecting streams '{}'",
+            log_names(connection.streams.iter().map(String::as_str))
+        );
+        connection.reconnect();
+
+        BinanceWebsocketMessage::Disconnected(self.connection_subscriptions(idx))
+    }
+
+    // Flags subscriptions that received nothing within their idle timeout
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Option<BinanceWebsocketMessage> {
+        loop {
+            let now = std::time::Instant::now();
+            let mut next: Option<std::time::Instant> = None;
+
+            for (name, timeout) in &self.idle_timeouts {
+                let stats = match self.stats.get_mut(name) {
+                    Some(stats) if !stats.stale => stats,
+                    _ => continue,
+                };
+
+                let deadline = stats.last_received + *timeout;
+                if deadline <= now {
+                    warn!(
+                        "[Websocket] No data on '{}' for {:?}",
+                        log_name(name),
+                        timeout
+                    );
+                    stats.stale = true;
+                    return self
+                        .subscriptions
+                        .get(name)
+                        .cloned()
+                        .map(BinanceWebsocketMessage::Stale);
+                }
+                next = Some(next.map_or(deadline, |next| next.min(deadline)));
+            }
+
+            let deadline = Instant::from_std(next?);
+            let timer = self.idle_timer.get_or_insert_with(|| delay_until(deadline));
+            if timer.deadline() != deadline {
+                timer.reset(deadline);
+            }
+            if Pin::new(timer).poll(cx).is_pending() {
+                return None;
+            }
+        }
+    }
+
+    fn record(&mut self, stream: &str, data: &Value) {
+        // Array streams such as `!ticker@arr` are measured by their first event
+        let event = if data.is_array() { &data[0] } else { data };
+        let lag_ms = event
+            .get("E")
+            .and_then(Value::as_i64)
+            .map(|event_time| Utc::now().timestamp_millis() - event_time);
+
+        if self.subscriptions.contains_key(stream) {
+            trace!(stream = log_name(stream), lag_ms, "[Websocket] Message");
+            #[cfg(feature = "metrics")]
+            crate::telemetry::message(log_name(stream), lag_ms);
+        }
+
+        if let Some(stats) = self.stats.get_mut(stream) {
+            stats.messages += 1;
+            stats.last_received = std::time::Instant::now();
+            stats.stale = false;
+            if lag_ms.is_some() {
+                stats.latency_ms = lag_ms;
+            }
+        }
+    }
+
+    pub(crate) fn poll_routed(&mut self, cx: &mut Context<'_>) -> Poll<Option<Routed>> {
+        if let Some(item) = self.buffer.pop_front() {
+            return Poll::Ready(Some(item));
+        }
+
+        self.poll_connections(cx)
+    }
+
+    fn poll_connections(&mut self, cx: &mut Context<'_>) -> Poll<Option<Routed>> {
+        'poll: loop {
+            if self.connections.is_empty() {
+                return Poll::Ready(Some((None, Err(Error::NoStreamSubscribed.into()))));
+            }
+
+            if let Some(stale) = self.poll_idle(cx) {
+                return Poll::Ready(Some((None, Ok(stale))));
+            }
+
+            let n = self.connections.len();
+            for i in 0..n {
+                let idx = (self.next_poll + i) % n;
+                let connection = &mut self.connections[idx];
+
+                if connection.is_open() && connection.connected_at.elapsed() >= MAX_CONNECTION_AGE {
+                    debug!("[Websocket] Rotating connection ahead of the 24h disconnect");
+                    connection.attempts = 0;
+                    return Poll::Ready(Some((None, Ok(self.disconnect(idx)))));
                 }
-                StreamYield::Finished(_) => Poll::Pending,
+
+                match &mut connection.socket {
+                    Socket::Open(socket) => {
+                        if let Err(e) = poll_pongs(socket, &mut connection.pongs, cx) {
+                            warn!("[Websocket] Connection error: {}", e);
+                            return Poll::Ready(Some((None, Ok(self.disconnect(idx)))));
+                        }
+
+                        match Pin::new(socket).poll_next(cx) {
+                            Poll::Ready(Some(Ok(msg))) => {
+                                if let Message::Ping(payload) = &msg {
+                                    connection.pongs.push_back(payload.clone());
+                                }
+                                self.next_poll = (idx + 1) % n;
+                                match self.parse_message(idx, msg) {
+                                    Ok(Incoming::Message(subscription, message)) => {
+                                        return Poll::Ready(Some((subscription, Ok(message))))
+                                    }
+                                    Ok(Incoming::Response(id, response)) => {
+                                        self.requests.remove(&id);
+                                        self.responses.insert(id, response);
+                                        continue 'poll;
+                                    }
+                                    Ok(Incoming::Closed) => {
+                                        return Poll::Ready(Some((None, Ok(self.disconnect(idx)))))
+                                    }
+                                    Ok(Incoming::Ignored) => continue 'poll,
+                                    Err(e) => return Poll::Ready(Some((None, Err(e)))),
+                                }
+                            }
+                            Poll::Ready(Some(Err(e))) => {
+                                warn!("[Websocket] Connection error: {}", e);
+                                return Poll::Ready(Some((None, Ok(self.disconnect(idx)))));
+                            }
+                            Poll::Ready(None) => {
+                                return Poll::Ready(Some((None, Ok(self.disconnect(idx)))))
+                            }
+                            Poll::Pending => {}
+                        }
+                    }
+                    Socket::Reconnecting(connecting) => match connecting.as_mut().poll(cx) {
+                        Poll::Ready(Ok(socket)) => {
+                            connection.socket = Socket::Open(socket);
+                            connection.connected_at = Instant::now();
+                            connection.attempts = 0;
+                            info!(
+                                "[Websocket] Reconnected streams '{}'",
+                                log_names(connection.streams.iter().map(String::as_str))
+                            );
+
+                            let subscriptions = self.connection_subscriptions(idx);
+                            return Poll::Ready(Some((
+                                None,
+                                Ok(BinanceWebsocketMessage::Reconnected(subscriptions)),
+                            )));
+                        }
+                        Poll::Ready(Err(e)) => {
+                            warn!(
+                                "[Websocket] Reconnection attempt {} failed: {}",
+                                connection.attempts, e
+                            );
+                            connection.reconnect();
+                            continue 'poll;
+                        }
+                        Poll::Pending => {}
+                    },
+                }
+            }
+
+            return Poll::Pending;
+        }
+    }
+
+    fn parse_message(&mut self, idx: usize, msg: Message) -> Fallible<Incoming> {
+        let msg = match msg {
+            Message::Text(msg) => msg,
+            Message::Binary(b) => {
+                return Ok(Incoming::Message(None, BinanceWebsocketMessage::Binary(b)))
+            }
+            Message::Pong(..) => return Ok(Incoming::Message(None, BinanceWebsocketMessage::Pong)),
+            Message::Ping(..) => return Ok(Incoming::Message(None, BinanceWebsocketMessage::Ping)),
+            Message::Close(..) => return Ok(Incoming::Closed),
+        };
+
+        let (stream, data) = match from_str(&msg) {
+            Ok(IncomingMessage::Stream { stream, data }) => (stream, data),
+            Ok(IncomingMessage::Response { id, result }) => {
+                return Ok(Incoming::Response(id, Ok(result)))
+            }
+            Ok(IncomingMessage::Error { id, code, msg }) => {
+                return Ok(Incoming::Response(
+                    id,
+                    Err(Error::BinanceError { code, msg }.into()),
+                ))
+            }
+            // Raw payload, only routable when the connection carries a single stream
+            Err(e) => match self.connections.get(idx).map(|c| &c.streams) {
+                Some(streams) if streams.len() == 1 => {
+                    (streams.iter().next().unwrap().clone(), from_str(&msg)?)
+                }
+                _ => return Err(e.into()),
             },
-            Poll::Ready(None) => Poll::Ready(Some(Err(Error::NoStreamSubscribed.into()))),
-            Poll::Pending => Poll::Pending,
+        };
+
+        // User data messages name the listen key
+        if log_name(&stream) == stream {
+            trace!("Incoming websocket message {}", msg);
+        }
+
+        self.record(&stream, &data);
+        if let Some(sub) = self.subscriptions.get(&stream) {
+            Ok(Incoming::Message(Some(sub.clone()), parse_data(sub, data)?))
+        } else {
+            trace!(
+                "[Websocket] Dropping message for unknown stream '{}'",
+                log_name(&stream)
+            );
+            Ok(Incoming::Ignored)
         }
     }
 }
 
-fn parse_message(sub: &Subscription, msg: Message) -> Fallible<BinanceWebsocketMessage> {
-    let msg = match msg {
-        Message::Text(msg) => msg,
-        Message::Binary(b) => return Ok(BinanceWebsocketMessage::Binary(b)),
-        Message::Pong(..) => return Ok(BinanceWebsocketMessage::Pong),
-        Message::Ping(..) => return Ok(BinanceWebsocketMessage::Ping),
-        Message::Close(..) => return Err(failure::format_err!("Socket closed")),
-    };
+impl Stream for BinanceWebsocket {
+    type Item = Fallible<BinanceWebsocketMessage>;
 
-    trace!("Incoming websocket message {}", msg);
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_routed(cx)
+            .map(|item| item.map(|(_, message)| message))
+    }
+}
+
+fn parse_data(sub: &Subscription, data: Value) -> Fallible<BinanceWebsocketMessage> {
     let message = match sub {
         Subscription::AggregateTrade(..) => {
-            BinanceWebsocketMessage::AggregateTrade(from_str(&msg)?)
-        }
-        Subscription::Candlestick(..) => BinanceWebsocketMessage::Candlestick(from_str(&msg)?),
-        Subscription::Depth(..) => BinanceWebsocketMessage::Depth(from_str(&msg)?),
-        Subscription::MiniTicker(..) => BinanceWebsocketMessage::MiniTicker(from_str(&msg)?),
-        Subscription::MiniTickerAll => BinanceWebsocketMessage::MiniTickerAll(from_str(&msg)?),
-        Subscription::OrderBook(..) => BinanceWebsocketMessage::OrderBook(from_str(&msg)?),
-        Subscription::Ticker(..) => BinanceWebsocketMessage::Ticker(from_str(&msg)?),
-        Subscription::TickerAll => BinanceWebsocketMessage::TickerAll(from_str(&msg)?),
-        Subscription::Trade(..) => BinanceWebsocketMessage::Trade(from_str(&msg)?),
-        Subscription::UserData(..) => {
-            let msg: Either<AccountUpdate, UserOrderUpdate> = from_str(&msg)?;
-            match msg {
-                Either::Left(m) => BinanceWebsocketMessage::UserAccountUpdate(m),
-                Either::Right(m) => BinanceWebsocketMessage::UserOrderUpdate(m),
-            }
+            BinanceWebsocketMessage::AggregateTrade(from_value(data)?)
         }
+        Subscription::BookTicker(..) | Subscription::BookTickerAll => {
+            BinanceWebsocketMessage::BookTicker(from_value(data)?)
+        }
+        Subscription::Candlestick(..) => BinanceWebsocketMessage::Candlestick(from_value(data)?),
+        Subscription::Depth(..) => BinanceWebsocketMessage::Depth(from_value(data)?),
+        Subscription::MiniTicker(..) => BinanceWebsocketMessage::MiniTicker(from_value(data)?),
+        Subscription::MiniTickerAll => BinanceWebsocketMessage::MiniTickerAll(from_value(data)?),
+        Subscription::OrderBook(..) => BinanceWebsocketMessage::OrderBook(from_value(data)?),
+        Subscription::Ticker(..) => BinanceWebsocketMessage::Ticker(from_value(data)?),
+        Subscription::TickerAll => BinanceWebsocketMessage::TickerAll(from_value(data)?),
+        Subscription::Trade(..) => BinanceWebsocketMessage::Trade(from_value(data)?),
+        Subscription::UserData(..) => BinanceWebsocketMessage::UserData(from_value(data)?),
     };
     Ok(message)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
-enum Either<L, R> {
-    Left(L),
-    Right(R),
+#[cfg(test)]
+mod test {
+    use super::{
+        backoff, endpoint, log_endpoint, log_name, BinanceWebsocket, Incoming, StreamStats,
+        MAX_BACKOFF, MAX_BUFFERED,
+    };
+    use crate::config::ClientConfig;
+    use crate::model::websocket::{BinanceWebsocketMessage, DepthLevel, Subscription, UpdateSpeed};
+    use failure::Fallible;
+    use futures::{channel::oneshot, prelude::*, task::noop_waker_ref};
+    use serde_json::{from_str, json, Value};
+    use std::{
+        task::{Context, Poll},
+        time::Duration,
+    };
+    use tokio::{
+        net::TcpListener,
+        time::{delay_for, timeout},
+    };
+    use tungstenite::Message;
+    use url::Url;
+
+    #[test]
+    fn combined_message_routing() -> Fallible<()> {
+        let mut ws = BinanceWebsocket::default();
+        let sub = Subscription::Trade("BNBBTC".into());
+        ws.subscriptions.insert(sub.stream_name(), sub);
+
+        let trade = r#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":123456789,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","b":88,"a":50,"T":123456785,"m":true,"M":true}}"#;
+        match ws.parse_message(0, Message::Text(trade.into()))? {
+            Incoming::Message(Some(_), BinanceWebsocketMessage::Trade(trade)) => {
+                assert_eq!(trade.trade_id, 12345);
+            }
+            _ => panic!("expected a trade message"),
+        }
+
+        let unknown = r#"{"stream":"ethbtc@trade","data":{}}"#;
+        assert!(matches!(
+            ws.parse_message(0, Message::Text(unknown.into()))?,
+            Incoming::Ignored
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn book_ticker() -> Fallible<()> {
+        let mut ws = BinanceWebsocket::default();
+        for sub in &[
+            Subscription::BookTicker("BNBUSDT".into()),
+            Subscription::BookTickerAll,
+        ] {
+            ws.subscriptions.insert(sub.stream_name(), sub.clone());
+        }
+
+        let update = r#"{"u":400900217,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}"#;
+        for stream in &["bnbusdt@bookTicker", "!bookTicker"] {
+            let msg = format!(r#"{{"stream":"{}","data":{}}}"#, stream, update);
+            match ws.parse_message(0, Message::Text(msg))? {
+                Incoming::Message(Some(_), BinanceWebsocketMessage::BookTicker(ticker)) => {
+                    assert_eq!(ticker.update_id, 400_900_217);
+                    assert!((ticker.best_ask - 25.3652).abs() < f64::EPSILON);
+                    assert!((ticker.best_bid_qty - 31.21).abs() < f64::EPSILON);
+                }
+                _ => panic!("expected a book ticker update"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn depth_streams() -> Fallible<()> {
+        let mut ws = BinanceWebsocket::default();
+        let partial =
+            Subscription::OrderBook("BNBBTC".into(), DepthLevel::Twenty, UpdateSpeed::Millis100);
+        let diff = Subscription::Depth("BNBBTC".into(), UpdateSpeed::Millis1000);
+        assert_eq!(partial.stream_name(), "bnbbtc@depth20@100ms");
+        assert_eq!(diff.stream_name(), "bnbbtc@depth");
+        ws.subscriptions.insert(partial.stream_name(), partial);
+        ws.subscriptions.insert(diff.stream_name(), diff);
+
+        let book = r#"{"stream":"bnbbtc@depth20@100ms","data":{"lastUpdateId":160,"bids":[["0.0024","10"]],"asks":[["0.0026","100"]]}}"#;
+        match ws.parse_message(0, Message::Text(book.into()))? {
+            Incoming::Message(_, BinanceWebsocketMessage::OrderBook(book)) => {
+                assert_eq!(book.last_update_id, 160);
+                assert_eq!(book.bids.len(), 1);
+            }
+            _ => panic!("expected a partial book"),
+        }
+
+        let update = r#"{"stream":"bnbbtc@depth","data":{"e":"depthUpdate","E":123456789,"s":"BNBBTC","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"]]}}"#;
+        match ws.parse_message(0, Message::Text(update.into()))? {
+            Incoming::Message(_, BinanceWebsocketMessage::Depth(depth)) => {
+                assert_eq!(depth.first_update_id, 157);
+                assert_eq!(depth.final_update_id, 160);
+            }
+            _ => panic!("expected a depth update"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn method_responses() -> Fallible<()> {
+        let mut ws = BinanceWebsocket::default();
+
+        let ok = r#"{"result":["btcusdt@aggTrade"],"id":3}"#;
+        match ws.parse_message(0, Message::Text(ok.into()))? {
+            Incoming::Response(3, Ok(result)) => assert_eq!(result[0], "btcusdt@aggTrade"),
+            _ => panic!("expected a successful response"),
+        }
+
+        let subscribed = r#"{"result":null,"id":4}"#;
+        assert!(matches!(
+            ws.parse_message(0, Message::Text(subscribed.into()))?,
+            Incoming::Response(4, Ok(_))
+        ));
+
+        let err = r#"{"code":0,"msg":"Unknown property","id":5}"#;
+        assert!(matches!(
+            ws.parse_message(0, Message::Text(err.into()))?,
+            Incoming::Response(5, Err(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn live_subscribe() -> Fallible<()> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let config = ClientConfig {
+            stream_url: Some(Url::parse(&format!(
+                "ws://{}/stream",
+                listener.local_addr()?
+            ))?),
+            ..ClientConfig::default()
+        };
+
+        // Answers the request and sends nothing else, so the response is the
+        // last frame read before the connection goes quiet
+        let (answered, on_answer) = oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut socket = tokio_tungstenite::accept_async(stream).await?;
+            let mut answered = Some(answered);
+            while let Some(msg) = socket.next().await {
+                if let Message::Text(text) = msg? {
+                    let id = from_str::<Value>(&text)?["id"].clone();
+                    let response = json!({ "result": null, "id": id });
+                    socket.send(Message::Text(response.to_string())).await?;
+                    answered.take().map(|answered| answered.send(()));
+                }
+            }
+            Ok::<_, failure::Error>(())
+        });
+
+        let mut ws = BinanceWebsocket::with_config(config);
+        ws.subscribe(Subscription::Trade("bnbbtc".into())).await?;
+
+        // Polled by hand, the socket may wake the task spuriously and hide a
+        // response that was read but not returned
+        let ethbtc = Subscription::Trade("ethbtc".into());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut subscribe = Box::pin(ws.subscribe(ethbtc.clone()));
+        assert!(subscribe.as_mut().poll(&mut cx).is_pending());
+        timeout(Duration::from_secs(5), on_answer).await??;
+        delay_for(Duration::from_millis(100)).await;
+        match subscribe.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => result?,
+            Poll::Pending => panic!("response was read but not returned"),
+        }
+        drop(subscribe);
+        assert!(ws.stats(&ethbtc).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_stats() -> Fallible<()> {
+        let mut ws = BinanceWebsocket::default();
+        let sub = Subscription::Trade("BNBBTC".into());
+        ws.subscriptions.insert(sub.stream_name(), sub.clone());
+        ws.stats.insert(sub.stream_name(), StreamStats::new());
+        ws.stats.get_mut(&sub.stream_name()).unwrap().stale = true;
+
+        let trade = r#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":123456789,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","b":88,"a":50,"T":123456785,"m":true,"M":true}}"#;
+        ws.parse_message(0, Message::Text(trade.into()))?;
+
+        let stats = ws.stats(&sub).unwrap();
+        assert_eq!(stats.messages, 1);
+        assert!(!stats.stale);
+        assert!(stats.latency_ms.unwrap() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn buffer_limit() {
+        let mut ws = BinanceWebsocket::default();
+        for _ in 0..MAX_BUFFERED + 2 {
+            ws.buffer_item((None, Ok(BinanceWebsocketMessage::Ping)));
+        }
+        assert_eq!(ws.buffer.len(), MAX_BUFFERED);
+        assert_eq!(ws.buffer_dropped, 2);
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(0));
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(50), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn listen_key_redaction() {
+        let key = "pqia91ma19a5s61cv6a81va65sdf19v8a65a1a5s61cv6a81va65sdf19v8a65a1";
+        let market = vec![
+            Subscription::Trade("BNBBTC".into()),
+            Subscription::BookTickerAll,
+            Subscription::MiniTickerAll,
+            Subscription::OrderBook("BNBBTC".into(), DepthLevel::Five, UpdateSpeed::Millis100),
+        ];
+        for subscription in &market {
+            let name = subscription.stream_name();
+            assert_eq!(log_name(&name), name);
+        }
+        assert_eq!(log_name(key), "userData");
+
+        let streams = vec![key.to_string(), "bnbbtc@trade".to_string()];
+        let url = log_endpoint(&endpoint(
+            &ClientConfig::default(),
+            &streams.into_iter().collect(),
+        ));
+        assert!(!url.as_str().contains(key));
+        assert_eq!(
+            url.query_pairs().next().map(|(_, v)| v.into_owned()),
+            Some("bnbbtc@trade/userData".into())
+        );
+    }
 }
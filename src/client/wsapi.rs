@@ -0,0 +1,411 @@
+use crate::{
+    client::Binance,
+    error::{BinanceErrorData, Error},
+    model::{
+        AccountInformation, Order, OrderBook, OrderCanceled, ServerTime, SymbolPrice, Transaction,
+    },
+    transport::Transport,
+};
+use chrono::Utc;
+use failure::Fallible;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, AbortHandle},
+    prelude::*,
+    stream::SplitStream,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{from_str, from_value, json, Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+use tokio::{net::TcpStream, runtime::Handle};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::*;
+use tungstenite::Message;
+use url::Url;
+
+const WS_API_URL: &str = "wss://ws-api.binance.com:443/ws-api/v3";
+
+type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Requests awaiting their response by id, `None` once the socket is closed
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Fallible<Value>>>>>>;
+
+#[derive(Debug, Deserialize)]
+struct WsApiResponse {
+    id: Option<u64>,
+    status: u16,
+    #[serde(default)]
+    result: Value,
+    error: Option<BinanceErrorData>,
+}
+
+impl WsApiResponse {
+    fn into_result(self) -> Fallible<Value> {
+        match self.error {
+            Some(BinanceErrorData { code, msg }) => Err(Error::BinanceError { code, msg }.into()),
+            None if self.status >= 400 => Err(failure::format_err!(
+                "Request failed with status {}",
+                self.status
+            )),
+            None => Ok(self.result),
+        }
+    }
+}
+
+// Client for the WebSocket API, requests are answered over a single persistent
+// connection. Requests may be pipelined, responses are matched to them by id.
+pub struct BinanceWsApi {
+    transport: Transport,
+    frames: mpsc::UnboundedSender<Message>,
+    pending: Pending,
+    reader: AbortHandle,
+    next_id: AtomicU64,
+    // Authenticated with session.logon, requests carry no signature
+    logged_on: AtomicBool,
+}
+
+impl BinanceWsApi {
+    pub async fn connect(binance: &Binance) -> Fallible<Self> {
+        let runtime = Handle::try_current()
+            .map_err(|_| failure::format_err!("No tokio runtime to run the WebSocket API on"))?;
+        let config = binance.transport.config();
+        let url = match &config.ws_api_url {
+            Some(url) => url.clone(),
+            None => Url::parse(WS_API_URL)?,
+        };
+        trace!("[WsApi] Connecting to '{}'", url);
+        let socket = config.connect_websocket(url).await?;
+
+        let (sink, stream) = socket.split();
+        let (frames, outgoing) = mpsc::unbounded();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let (reader, abort) = future::abortable(read(stream, frames.clone(), pending.clone()));
+        runtime.spawn(reader);
+        runtime.spawn(outgoing.map(Ok).forward(sink).map(|result| {
+            if let Err(e) = result {
+                warn!("[WsApi] Failed to send frame: {}", e);
+            }
+        }));
+
+        Ok(Self {
+            transport: binance.transport.clone(),
+            frames,
+            pending,
+            reader: abort,
+            next_id: AtomicU64::new(0),
+            logged_on: AtomicBool::new(false),
+        })
+    }
+
+    // Sends a method frame and waits for the response with the matching id
+    pub async fn request(&self, method: &str, params: Value) -> Fallible<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut frame = json!({ "id": id, "method": method });
+        if !params.is_null() {
+            frame["params"] = params;
+        }
+
+        let (reply, response) = oneshot::channel();
+        self.register(id, reply)?;
+
+        trace!("[WsApi] Sending {} request {}", method, id);
+        if self
+            .frames
+            .unbounded_send(Message::Text(frame.to_string()))
+            .is_err()
+        {
+            self.forget(id);
+            return Err(failure::format_err!("Socket closed"));
+        }
+
+        response
+            .await
+            .map_err(|_| failure::format_err!("Socket closed"))?
+    }
+
+    fn register(&self, id: u64, reply: oneshot::Sender<Fallible<Value>>) -> Fallible<()> {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .ok_or_else(|| failure::format_err!("Socket closed"))?
+            .insert(id, reply);
+        Ok(())
+    }
+
+    fn forget(&self, id: u64) {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pending) = pending.as_mut() {
+            pending.remove(&id);
+        }
+    }
+
+    pub async fn signed_request(&self, method: &str, params: Value) -> Fallible<Value> {
+        let params = if self.logged_on.load(Ordering::Relaxed) {
+            let mut params = object(params)?;
+            params.insert("timestamp".into(), Utc::now().timestamp_millis().into());
+            Value::Object(params)
+        } else {
+            sign(&self.transport, params, Utc::now().timestamp_millis())?
+        };
+        self.request(method, params).await
+    }
+
+    // Authenticates the connection so later requests need no signature.
+    // Binance only accepts Ed25519 keys for this.
+    pub async fn session_logon(&self) -> Fallible<()> {
+        let params = sign(&self.transport, json!({}), Utc::now().timestamp_millis())?;
+        self.request("session.logon", params).await?;
+        self.logged_on.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn session_logout(&self) -> Fallible<()> {
+        self.request("session.logout", Value::Null).await?;
+        self.logged_on.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Fallible<T> {
+        Ok(from_value(self.request(method, params).await?)?)
+    }
+
+    async fn signed_call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Fallible<T> {
+        Ok(from_value(self.signed_request(method, params).await?)?)
+    }
+
+    // Test connectivity
+    pub async fn ping(&self) -> Fallible<()> {
+        self.request("ping", Value::Null).await?;
+        Ok(())
+    }
+
+    pub async fn get_server_time(&self) -> Fallible<ServerTime> {
+        self.call("time", Value::Null).await
+    }
+
+    pub async fn get_depth(&self, symbol: &str, limit: u64) -> Fallible<OrderBook> {
+        self.call("depth", json!({"symbol": symbol, "limit": limit}))
+            .await
+    }
+
+    // Latest price for ONE symbol.
+    pub async fn get_price(&self, symbol: &str) -> Fallible<f64> {
+        let price: SymbolPrice = self
+            .call("ticker.price", json!({ "symbol": symbol }))
+            .await?;
+        Ok(price.price)
+    }
+
+    pub async fn get_account(&self) -> Fallible<AccountInformation> {
+        self.signed_call("account.status", json!({})).await
+    }
+
+    pub async fn get_open_orders(&self, symbol: &str) -> Fallible<Vec<Order>> {
+        self.signed_call("openOrders.status", json!({ "symbol": symbol }))
+            .await
+    }
+
+    pub async fn order_status(&self, symbol: &str, order_id: u64) -> Fallible<Order> {
+        self.signed_call(
+            "order.status",
+            json!({"symbol": symbol, "orderId": order_id}),
+        )
+        .await
+    }
+
+    pub async fn limit_buy(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        self.place_order(symbol, "BUY", qty, Some(price)).await
+    }
+
+    pub async fn limit_sell(&self, symbol: &str, qty: f64, price: f64) -> Fallible<Transaction> {
+        self.place_order(symbol, "SELL", qty, Some(price)).await
+    }
+
+    pub async fn market_buy(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        self.place_order(symbol, "BUY", qty, None).await
+    }
+
+    pub async fn market_sell(&self, symbol: &str, qty: f64) -> Fallible<Transaction> {
+        self.place_order(symbol, "SELL", qty, None).await
+    }
+
+    pub async fn cancel_order(&self, symbol: &str, order_id: u64) -> Fallible<OrderCanceled> {
+        self.signed_call(
+            "order.cancel",
+            json!({"symbol": symbol, "orderId": order_id}),
+        )
+        .await
+    }
+
+    async fn place_order(
+        &self,
+        symbol: &str,
+        side: &str,
+        qty: f64,
+        price: Option<f64>,
+    ) -> Fallible<Transaction> {
+        let mut params = json!({
+            "symbol": symbol,
+            "side": side,
+            "type": "MARKET",
+            "quantity": qty.to_string(),
+        });
+        if let Some(price) = price {
+            params["type"] = "LIMIT".into();
+            params["price"] = price.to_string().into();
+            params["timeInForce"] = "GTC".into();
+        }
+        self.signed_call("order.place", params).await
+    }
+}
+
+impl Drop for BinanceWsApi {
+    fn drop(&mut self) {
+        // The writer stops once the reader's sender is gone too
+        self.reader.abort();
+    }
+}
+
+// Completes pending requests with the responses read from the socket and
+// fails the remaining ones once it closes
+async fn read(
+    mut stream: SplitStream<WSStream>,
+    frames: mpsc::UnboundedSender<Message>,
+    pending: Pending,
+) {
+    while let Some(msg) = stream.next().await {
+        match msg {
+            Ok(Message::Text(msg)) => {
+                let (id, result) = match from_str::<WsApiResponse>(&msg) {
+                    Ok(response) => (response.id, response.into_result()),
+                    // Still fail the request the frame answers, if it names one
+                    Err(e) => {
+                        warn!("[WsApi] Failed to parse response {}: {}", msg, e);
+                        let id = from_str::<Value>(&msg)
+                            .ok()
+                            .and_then(|value| value["id"].as_u64());
+                        (id, Err(e.into()))
+                    }
+                };
+                let reply = id.and_then(|id| {
+                    let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+                    pending.as_mut().and_then(|pending| pending.remove(&id))
+                });
+                if let Some(reply) = reply {
+                    let _ = reply.send(result);
+                } else {
+                    trace!("[WsApi] Dropping response to request {:?}", id);
+                }
+            }
+            Ok(Message::Ping(payload)) => {
+                let _ = frames.unbounded_send(Message::Pong(payload));
+            }
+            Ok(Message::Close(..)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("[WsApi] Socket error: {}", e);
+                break;
+            }
+        }
+    }
+
+    trace!("[WsApi] Socket closed");
+    // Dropping the senders fails the requests still waiting
+    pending
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+}
+
+// Adds the api key, timestamp and signature over the sorted parameters
+fn sign(transport: &Transport, params: Value, timestamp: i64) -> Fallible<Value> {
+    let mut params = object(params)?;
+    let recv_window = transport.recv_window()?;
+    params
+        .entry("recvWindow")
+        .or_insert_with(|| recv_window.into());
+    params.insert("timestamp".into(), timestamp.into());
+    params.insert("apiKey".into(), transport.api_key()?.into());
+
+    let sign_message = params
+        .iter()
+        .map(|(k, v)| match v {
+            Value::String(s) => format!("{}={}", k, s),
+            other => format!("{}={}", k, other),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let (_, signature) = transport.sign(&sign_message)?;
+    params.insert("signature".into(), signature.into());
+
+    Ok(Value::Object(params))
+}
+
+fn object(params: Value) -> Fallible<Map<String, Value>> {
+    match params {
+        Value::Object(params) => Ok(params),
+        Value::Null => Ok(Map::new()),
+        other => Err(failure::format_err!(
+            "Expected object parameters, got {}",
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WsApiResponse;
+    use crate::transport::Transport;
+    use failure::Fallible;
+    use serde_json::{from_str, json};
+
+    #[test]
+    fn response_errors() -> Fallible<()> {
+        let ok: WsApiResponse =
+            from_str(r#"{"id":1,"status":200,"result":{"serverTime":1656400526260}}"#)?;
+        assert_eq!(ok.into_result()?["serverTime"], 1_656_400_526_260_u64);
+
+        let err: WsApiResponse = from_str(
+            r#"{"id":2,"status":400,"error":{"code":-2010,"msg":"Account has insufficient balance for requested action."}}"#,
+        )?;
+        assert!(err.into_result().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn signature_params() -> Fallible<()> {
+        let transport = Transport::with_credential(
+            "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A",
+            "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j",
+        );
+        let params = json!({
+            "symbol": "BTCUSDT",
+            "side": "SELL",
+            "type": "LIMIT",
+            "timeInForce": "GTC",
+            "quantity": "0.01000000",
+            "price": "52000.00",
+            "newOrderRespType": "ACK",
+            "recvWindow": 100,
+        });
+
+        let signed = super::sign(&transport, params, 1_645_423_376_532)?;
+        assert_eq!(
+            signed["signature"],
+            "cc15477742bd704c29492d96c7ead9414dfd8e0ec4a00f947bb5bb454ddbd08a"
+        );
+        assert_eq!(
+            signed["apiKey"],
+            "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A"
+        );
+        Ok(())
+    }
+}
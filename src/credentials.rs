@@ -0,0 +1,107 @@
+use crate::error::Error;
+use failure::Fallible;
+use serde::Deserialize;
+use std::{env, fmt, fs, path::Path};
+use zeroize::{Zeroize, Zeroizing};
+
+const API_KEY_VAR: &str = "BINANCE_API_KEY";
+const API_SECRET_VAR: &str = "BINANCE_API_SECRET";
+
+// String that is wiped from memory on drop and never printed
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    #[must_use]
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(s: &str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+// API key and secret pair for HMAC signed requests
+#[derive(Clone, Debug, Deserialize)]
+pub struct Credentials {
+    pub api_key: Secret,
+    pub api_secret: Secret,
+}
+
+impl Credentials {
+    #[must_use]
+    pub fn new(api_key: &str, api_secret: &str) -> Self {
+        Self {
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+        }
+    }
+
+    // Reads BINANCE_API_KEY and BINANCE_API_SECRET
+    pub fn from_env() -> Fallible<Self> {
+        let var = |name: &str| {
+            env::var(name)
+                .map(Secret)
+                .map_err(|_| Error::MissingCredential {
+                    name: name.to_string(),
+                })
+        };
+
+        Ok(Self {
+            api_key: var(API_KEY_VAR)?,
+            api_secret: var(API_SECRET_VAR)?,
+        })
+    }
+
+    // JSON file with "api_key" and "api_secret" fields
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Fallible<Self> {
+        let contents = Zeroizing::new(fs::read_to_string(path)?);
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Credentials;
+    use failure::Fallible;
+    use std::fs;
+
+    #[test]
+    fn redacted_credentials() -> Fallible<()> {
+        let path = std::env::temp_dir().join("binance-async-credentials.json");
+        fs::write(&path, r#"{"api_key": "my-key", "api_secret": "my-secret"}"#)?;
+        let credentials = Credentials::from_file(&path);
+        fs::remove_file(&path)?;
+        let credentials = credentials?;
+
+        assert_eq!(credentials.api_key.expose(), "my-key");
+        assert_eq!(credentials.api_secret.expose(), "my-secret");
+
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("my-key"));
+        assert!(!debug.contains("my-secret"));
+        Ok(())
+    }
+}
@@ -0,0 +1,697 @@
+// Matching engine shared by the mock server and `PaperBinance`. Parts of it
+// only serve the mock server.
+#![cfg_attr(not(feature = "mock"), allow(dead_code))]
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryFrom,
+};
+
+// HTTP status, Binance error code and message of a rejected request
+pub type Rejection = (u16, i64, String);
+
+pub fn reject(code: i64, msg: &str) -> Rejection {
+    (400, code, msg.to_string())
+}
+
+fn now() -> u64 {
+    u64::try_from(Utc::now().timestamp_millis()).unwrap_or_default()
+}
+
+fn param<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a str, Rejection> {
+    params.get(name).map(String::as_str).ok_or_else(|| {
+        reject(
+            -1102,
+            &format!(
+                "Mandatory parameter '{}' was not sent, was empty/null, or malformed.",
+                name
+            ),
+        )
+    })
+}
+
+fn order_id(params: &HashMap<String, String>) -> Result<u64, Rejection> {
+    param(params, "orderId")?
+        .parse()
+        .map_err(|_| reject(-1100, "Illegal characters found in parameter 'orderId'."))
+}
+
+fn number(params: &HashMap<String, String>, name: &str) -> Result<f64, Rejection> {
+    param(params, name)?.parse().map_err(|_| {
+        reject(
+            -1100,
+            &format!("Illegal characters found in parameter '{}'.", name),
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Buy => "BUY",
+            Self::Sell => "SELL",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MockOrder {
+    id: u64,
+    client_order_id: String,
+    symbol: String,
+    side: Side,
+    kind: &'static str,
+    price: f64, // 0 for market orders
+    qty: f64,
+    executed: f64,
+    quote_qty: f64,
+    reserved: f64, // quote asset for buys, base asset for sells
+    status: &'static str,
+    time: u64,
+}
+
+impl MockOrder {
+    fn to_json(&self) -> Value {
+        json!({
+            "symbol": self.symbol,
+            "orderId": self.id,
+            "clientOrderId": self.client_order_id,
+            "price": format!("{:.8}", self.price),
+            "origQty": format!("{:.8}", self.qty),
+            "executedQty": format!("{:.8}", self.executed),
+            "status": self.status,
+            "timeInForce": "GTC",
+            "type": self.kind,
+            "side": self.side.as_str(),
+            "stopPrice": "0.00000000",
+            "icebergQty": "0.00000000",
+            "time": self.time,
+        })
+    }
+
+    // executionReport as sent on the user data stream
+    fn report(&self, execution: &str, fill: Option<&Fill>) -> Value {
+        let (trade_id, last_qty, last_price) = fill.map_or((-1, 0.0, 0.0), |fill| {
+            (
+                i64::try_from(fill.trade_id).unwrap_or(-1),
+                fill.qty,
+                fill.price,
+            )
+        });
+        json!({
+            "e": "executionReport",
+            "E": now(),
+            "s": self.symbol,
+            "c": self.client_order_id,
+            "S": self.side.as_str(),
+            "o": self.kind,
+            "f": "GTC",
+            "q": format!("{:.8}", self.qty),
+            "p": format!("{:.8}", self.price),
+            "P": "0.00000000",
+            "F": "0.00000000",
+            "g": -1,
+            "C": "",
+            "x": execution,
+            "X": self.status,
+            "r": "NONE",
+            "i": self.id,
+            "l": format!("{:.8}", last_qty),
+            "z": format!("{:.8}", self.executed),
+            "L": format!("{:.8}", last_price),
+            "n": format!("{:.8}", fill.map_or(0.0, |fill| fill.commission)),
+            "N": fill.map(|fill| fill.commission_asset.clone()),
+            "T": now(),
+            "t": trade_id,
+            "I": 0,
+            "w": self.status == "NEW",
+            "m": fill.is_some_and(|fill| fill.maker),
+            "M": false,
+            "O": self.time,
+            "Z": format!("{:.8}", self.quote_qty),
+            "Y": format!("{:.8}", last_qty * last_price),
+            "Q": "0.00000000",
+        })
+    }
+}
+
+// Execution of an order against the market
+#[derive(Debug, Clone)]
+struct Fill {
+    trade_id: u64,
+    qty: f64,
+    price: f64,
+    commission: f64,
+    commission_asset: String,
+    maker: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Market {
+    base: String,
+    quote: String,
+    price: f64, // last trade
+    bid: f64,
+    ask: f64,
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+    update_id: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Asset {
+    free: f64,
+    locked: f64,
+}
+
+// Exchange state behind the mock server and paper trading. Orders fill in full,
+// marketable ones at the best bid or ask, resting limit orders at their price
+// once the quotes or a trade cross them.
+#[derive(Debug, Default)]
+pub struct Exchange {
+    markets: BTreeMap<String, Market>,
+    balances: BTreeMap<String, Asset>,
+    // Maker and taker commission in basis points, as in `AccountInformation`
+    commission: (f64, f64),
+    orders: BTreeMap<u64, MockOrder>,
+    trades: Vec<(String, Value)>,
+    pub listen_keys: BTreeSet<String>,
+    next_id: u64,
+    // (stream, event) pairs waiting to be published
+    pub events: Vec<(String, Value)>,
+}
+
+impl Exchange {
+    pub fn add_symbol(&mut self, symbol: &str, base: &str, quote: &str, price: f64) {
+        self.markets.insert(
+            symbol.to_string(),
+            Market {
+                base: base.to_string(),
+                quote: quote.to_string(),
+                price,
+                bid: price,
+                ask: price,
+                ..Market::default()
+            },
+        );
+    }
+
+    pub fn set_balance(&mut self, asset: &str, free: f64) {
+        self.balances.entry(asset.to_string()).or_default().free = free;
+    }
+
+    pub fn has_symbol(&self, symbol: &str) -> bool {
+        self.markets.contains_key(symbol)
+    }
+
+    pub const fn set_commission(&mut self, maker: f64, taker: f64) {
+        self.commission = (maker, taker);
+    }
+
+    pub fn set_depth(&mut self, symbol: &str, bids: &[(f64, f64)], asks: &[(f64, f64)]) {
+        if let Some(market) = self.markets.get_mut(symbol) {
+            market.bids = bids.to_vec();
+            market.asks = asks.to_vec();
+            market.update_id += 1;
+        }
+    }
+
+    // Moves the last price and both quotes to `price`
+    pub fn set_price(&mut self, symbol: &str, price: f64) {
+        if let Some(market) = self.markets.get_mut(symbol) {
+            market.price = price;
+            market.bid = price;
+            market.ask = price;
+        }
+        self.cross(symbol, price, price);
+    }
+
+    // New best bid and ask, e.g. from the book ticker stream
+    pub fn quote(&mut self, symbol: &str, bid: f64, ask: f64) {
+        if let Some(market) = self.markets.get_mut(symbol) {
+            market.bid = bid;
+            market.ask = ask;
+        }
+        self.cross(symbol, ask, bid);
+    }
+
+    // Trade printed by the market, e.g. from the trade stream
+    pub fn trade(&mut self, symbol: &str, price: f64) {
+        if let Some(market) = self.markets.get_mut(symbol) {
+            market.price = price;
+        }
+        self.cross(symbol, price, price);
+    }
+
+    // Fills resting buys at or above `buy_at` and sells at or below `sell_at`
+    fn cross(&mut self, symbol: &str, buy_at: f64, sell_at: f64) {
+        let crossed: Vec<_> = self
+            .orders
+            .values()
+            .filter(|o| o.symbol == symbol && o.status == "NEW")
+            .filter(|o| match o.side {
+                Side::Buy => o.price >= buy_at,
+                Side::Sell => o.price <= sell_at,
+            })
+            .map(|o| (o.id, o.price))
+            .collect();
+        // Resting orders fill at their own limit price
+        for (id, limit) in crossed {
+            self.fill(id, limit, true);
+        }
+    }
+
+    fn market(&self, symbol: &str) -> Result<&Market, Rejection> {
+        self.markets
+            .get(symbol)
+            .ok_or_else(|| reject(-1121, "Invalid symbol."))
+    }
+
+    const fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn publish(&mut self, event: &Value) {
+        for key in &self.listen_keys {
+            self.events.push((key.clone(), event.clone()));
+        }
+    }
+
+    pub fn place(&mut self, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+        let symbol = param(params, "symbol")?.to_string();
+        let side = match param(params, "side")? {
+            "BUY" => Side::Buy,
+            "SELL" => Side::Sell,
+            _ => return Err(reject(-1117, "Invalid side.")),
+        };
+        let kind = match param(params, "type")? {
+            "LIMIT" => "LIMIT",
+            "MARKET" => "MARKET",
+            _ => return Err(reject(-1116, "Invalid orderType.")),
+        };
+        let qty = number(params, "quantity")?;
+        let price = if kind == "LIMIT" {
+            number(params, "price")?
+        } else {
+            0.0
+        };
+
+        let market = self.market(&symbol)?;
+        let (base, quote, bid, ask) = (
+            market.base.clone(),
+            market.quote.clone(),
+            market.bid,
+            market.ask,
+        );
+        let (asset, reserved) = match side {
+            Side::Buy if kind == "LIMIT" => (quote, qty * price),
+            Side::Buy => (quote, qty * ask),
+            Side::Sell => (base, qty),
+        };
+        let balance = self.balances.entry(asset).or_default();
+        if balance.free < reserved {
+            return Err(reject(
+                -2010,
+                "Account has insufficient balance for requested action.",
+            ));
+        }
+        balance.free -= reserved;
+        balance.locked += reserved;
+
+        let id = self.next_id();
+        let order = MockOrder {
+            id,
+            client_order_id: params
+                .get("newClientOrderId")
+                .cloned()
+                .unwrap_or_else(|| format!("mock{}", id)),
+            symbol: symbol.clone(),
+            side,
+            kind,
+            price,
+            qty,
+            executed: 0.0,
+            quote_qty: 0.0,
+            reserved,
+            status: "NEW",
+            time: now(),
+        };
+        self.publish(&order.report("NEW", None));
+        let transaction = json!({
+            "symbol": symbol,
+            "orderId": id,
+            "clientOrderId": order.client_order_id,
+            "transactTime": order.time,
+        });
+        self.orders.insert(id, order);
+
+        let marketable = match side {
+            _ if kind == "MARKET" => true,
+            Side::Buy => price >= ask,
+            Side::Sell => price <= bid,
+        };
+        if marketable {
+            self.fill(id, if side == Side::Buy { ask } else { bid }, false);
+        }
+
+        Ok(transaction)
+    }
+
+    fn fill(&mut self, id: u64, price: f64, maker: bool) {
+        let mut order = match self.orders.get(&id) {
+            Some(order) => order.clone(),
+            None => return,
+        };
+        let market = match self.markets.get(&order.symbol) {
+            Some(market) => market.clone(),
+            None => return,
+        };
+        let (qty, cost) = (order.qty, order.qty * price);
+        let rate = if maker {
+            self.commission.0
+        } else {
+            self.commission.1
+        } / 10_000.0;
+
+        // Commission comes out of the asset received
+        let (commission, commission_asset) = match order.side {
+            Side::Buy => {
+                let quote = self.balances.entry(market.quote.clone()).or_default();
+                quote.locked -= order.reserved;
+                quote.free += order.reserved - cost;
+                self.balances.entry(market.base.clone()).or_default().free += qty * (1.0 - rate);
+                (qty * rate, market.base.clone())
+            }
+            Side::Sell => {
+                self.balances.entry(market.base.clone()).or_default().locked -= order.reserved;
+                self.balances.entry(market.quote.clone()).or_default().free += cost * (1.0 - rate);
+                (cost * rate, market.quote.clone())
+            }
+        };
+
+        let fill = Fill {
+            trade_id: self.next_id(),
+            qty,
+            price,
+            commission,
+            commission_asset,
+            maker,
+        };
+        order.executed = qty;
+        order.quote_qty = cost;
+        order.status = "FILLED";
+        self.orders.insert(id, order.clone());
+        self.trades.push((
+            order.symbol.clone(),
+            json!({
+                "symbol": order.symbol,
+                "id": fill.trade_id,
+                "orderId": id,
+                "price": format!("{:.8}", price),
+                "qty": format!("{:.8}", qty),
+                "commission": format!("{:.8}", fill.commission),
+                "commissionAsset": fill.commission_asset,
+                "time": now(),
+                "isBuyer": order.side == Side::Buy,
+                "isMaker": maker,
+                "isBestMatch": true,
+            }),
+        ));
+        self.publish(&order.report("TRADE", Some(&fill)));
+        self.publish_position(&[&market.base, &market.quote]);
+    }
+
+    // outboundAccountPosition for the assets a fill changed
+    fn publish_position(&mut self, assets: &[&str]) {
+        let balances: Vec<_> = assets
+            .iter()
+            .map(|asset| {
+                let balance = self.balances.get(*asset).copied().unwrap_or_default();
+                json!({
+                    "a": asset,
+                    "f": format!("{:.8}", balance.free),
+                    "l": format!("{:.8}", balance.locked),
+                })
+            })
+            .collect();
+        self.publish(&json!({
+            "e": "outboundAccountPosition",
+            "E": now(),
+            "u": now(),
+            "B": balances,
+        }));
+    }
+
+    pub fn cancel(&mut self, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+        let symbol = param(params, "symbol")?;
+        let id = order_id(params)?;
+        let order = match self.orders.get_mut(&id) {
+            Some(order) if order.symbol == symbol && order.status == "NEW" => order,
+            _ => return Err(reject(-2011, "Unknown order sent.")),
+        };
+        order.status = "CANCELED";
+        let order = order.clone();
+
+        let market = self.market(symbol)?.clone();
+        let asset = match order.side {
+            Side::Buy => market.quote,
+            Side::Sell => market.base,
+        };
+        let balance = self.balances.entry(asset).or_default();
+        balance.locked -= order.reserved;
+        balance.free += order.reserved;
+
+        self.publish(&order.report("CANCELED", None));
+        Ok(json!({
+            "symbol": order.symbol,
+            "origClientOrderId": order.client_order_id,
+            "orderId": order.id,
+            "clientOrderId": format!("cancel{}", order.id),
+        }))
+    }
+
+    pub fn order(&self, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+        let symbol = param(params, "symbol")?;
+        let id = order_id(params)?;
+        self.orders
+            .get(&id)
+            .filter(|order| order.symbol == symbol)
+            .map(MockOrder::to_json)
+            .ok_or_else(|| reject(-2013, "Order does not exist."))
+    }
+
+    pub fn open_orders(&self, params: &HashMap<String, String>) -> Value {
+        let symbol = params.get("symbol");
+        self.orders
+            .values()
+            .filter(|o| o.status == "NEW" && (symbol.is_none() || symbol == Some(&o.symbol)))
+            .map(MockOrder::to_json)
+            .collect()
+    }
+
+    pub fn trades(&self, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+        let symbol = param(params, "symbol")?;
+        Ok(self
+            .trades
+            .iter()
+            .filter(|(s, _)| s == symbol)
+            .map(|(_, trade)| trade.clone())
+            .collect())
+    }
+
+    pub fn account(&self) -> Value {
+        let balances: Vec<_> = self
+            .balances
+            .iter()
+            .map(|(asset, balance)| {
+                json!({
+                    "asset": asset,
+                    "free": format!("{:.8}", balance.free),
+                    "locked": format!("{:.8}", balance.locked),
+                })
+            })
+            .collect();
+        json!({
+            "makerCommission": self.commission.0,
+            "takerCommission": self.commission.1,
+            "buyerCommission": 0,
+            "sellerCommission": 0,
+            "canTrade": true,
+            "canWithdraw": true,
+            "canDeposit": true,
+            "balances": balances,
+        })
+    }
+
+    pub fn depth(&self, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+        let market = self.market(param(params, "symbol")?)?;
+        let limit = params
+            .get("limit")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(100);
+        let levels = |levels: &[(f64, f64)]| -> Vec<Value> {
+            levels
+                .iter()
+                .take(limit)
+                .map(|(price, qty)| json!([format!("{:.8}", price), format!("{:.8}", qty)]))
+                .collect()
+        };
+        Ok(json!({
+            "lastUpdateId": market.update_id,
+            "bids": levels(&market.bids),
+            "asks": levels(&market.asks),
+        }))
+    }
+
+    pub fn exchange_info(&self) -> Value {
+        let symbols: Vec<_> = self
+            .markets
+            .iter()
+            .map(|(symbol, market)| {
+                json!({
+                    "symbol": symbol,
+                    "status": "TRADING",
+                    "baseAsset": market.base,
+                    "baseAssetPrecision": 8,
+                    "quoteAsset": market.quote,
+                    "quotePrecision": 8,
+                    "orderTypes": ["LIMIT", "MARKET"],
+                    "icebergAllowed": false,
+                    "filters": [],
+                })
+            })
+            .collect();
+        json!({
+            "timezone": "UTC",
+            "serverTime": now(),
+            "rateLimits": [
+                { "rateLimitType": "REQUEST_WEIGHT", "interval": "MINUTE", "limit": 1200 },
+            ],
+            "symbols": symbols,
+        })
+    }
+
+    pub fn prices(&self) -> Value {
+        self.markets
+            .iter()
+            .map(|(symbol, market)| json!({"symbol": symbol, "price": format!("{:.8}", market.price)}))
+            .collect()
+    }
+
+    pub fn book_tickers(&self) -> Value {
+        self.markets
+            .iter()
+            .map(|(symbol, market)| {
+                let (bid, bid_qty) = market.bids.first().copied().unwrap_or((market.bid, 0.0));
+                let (ask, ask_qty) = market.asks.first().copied().unwrap_or((market.ask, 0.0));
+                json!({
+                    "symbol": symbol,
+                    "bidPrice": format!("{:.8}", bid),
+                    "bidQty": format!("{:.8}", bid_qty),
+                    "askPrice": format!("{:.8}", ask),
+                    "askQty": format!("{:.8}", ask_qty),
+                })
+            })
+            .collect()
+    }
+
+    pub fn create_listen_key(&mut self) -> Value {
+        let key = format!("mockListenKey{}", self.next_id());
+        self.listen_keys.insert(key.clone());
+        json!({ "listenKey": key })
+    }
+
+    pub fn listen_key(
+        &mut self,
+        params: &HashMap<String, String>,
+        close: bool,
+    ) -> Result<Value, Rejection> {
+        let key = param(params, "listenKey")?;
+        let known = if close {
+            self.listen_keys.remove(key)
+        } else {
+            self.listen_keys.contains(key)
+        };
+        if known {
+            Ok(json!({}))
+        } else {
+            Err(reject(-1125, "This listenKey does not exist."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Exchange;
+    use maplit::hashmap;
+    use std::collections::HashMap;
+
+    fn balance(exchange: &Exchange, asset: &str, field: &str) -> String {
+        let account = exchange.account();
+        let balances = account["balances"].as_array().unwrap();
+        let balance = balances.iter().find(|b| b["asset"] == asset).unwrap();
+        balance[field].as_str().unwrap().to_string()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn matching() {
+        let mut exchange = Exchange::default();
+        exchange.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+        exchange.set_balance("BTC", 1.0);
+
+        // Rests below the market and locks the quote asset
+        let buy = params(&[
+            ("symbol", "BNBBTC"),
+            ("side", "BUY"),
+            ("type", "LIMIT"),
+            ("quantity", "100"),
+            ("price", "0.001"),
+        ]);
+        let id = exchange.place(&buy).unwrap()["orderId"].as_u64().unwrap();
+        assert_eq!(
+            exchange
+                .open_orders(&HashMap::new())
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(balance(&exchange, "BTC", "locked"), "0.10000000");
+
+        exchange.set_price("BNBBTC", 0.001);
+        let order = exchange.order(&params(&[
+            ("symbol", "BNBBTC"),
+            ("orderId", &id.to_string()),
+        ]));
+        assert_eq!(order.unwrap()["status"], "FILLED");
+        assert_eq!(balance(&exchange, "BNB", "free"), "100.00000000");
+        assert_eq!(balance(&exchange, "BTC", "free"), "0.90000000");
+
+        let sell = params(&[
+            ("symbol", "BNBBTC"),
+            ("side", "SELL"),
+            ("type", "MARKET"),
+            ("quantity", "200"),
+        ]);
+        assert_eq!(exchange.place(&sell).unwrap_err().1, -2010);
+
+        let cancel = hashmap! {"symbol".to_string() => "BNBBTC".to_string(), "orderId".to_string() => id.to_string()};
+        assert_eq!(exchange.cancel(&cancel).unwrap_err().1, -2011);
+    }
+}
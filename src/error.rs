@@ -12,8 +12,22 @@ pub enum Error {
     SymbolNotFound,
     #[snafu(display("No Api key set for private api"))]
     NoApiKeySet,
+    #[snafu(display("Credential '{}' is not set", name))]
+    MissingCredential { name: String },
+    #[snafu(display("recvWindow of {}ms is outside 1..=60000", recv_window))]
+    InvalidRecvWindow { recv_window: u64 },
+    #[snafu(display("Request to {} failed: {}", endpoint, reason))]
+    RequestFailed { endpoint: String, reason: String },
     #[snafu(display("No stream is subscribed"))]
     NoStreamSubscribed,
+    #[snafu(display("Subscriber fell more than {} messages behind", capacity))]
+    SubscriberLagged { capacity: usize },
+    #[snafu(display("Stream disconnected, reconnecting"))]
+    StreamDisconnected,
+    #[snafu(display("Stream reconnected, messages may have been missed"))]
+    StreamReconnected,
+    #[snafu(display("No data on the stream within its idle timeout"))]
+    StreamStale,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -2,8 +2,35 @@
 #![allow(clippy::missing_errors_doc)]
 
 mod client;
+pub mod config;
+mod credentials;
+mod engine;
 pub mod error;
+pub mod middleware;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod model;
+pub mod pool;
+mod response;
+mod signer;
+#[cfg(feature = "metrics")]
+pub mod telemetry;
 mod transport;
 
-pub use crate::client::{websocket::BinanceWebsocket, Binance};
+pub use crate::client::{
+    multiplexer::{FromMessage, LagPolicy, TypedStream, WebsocketHandle},
+    orderbook::{LocalOrderBook, TopOfBook},
+    paper::{PaperBinance, PaperUserStream},
+    traits::{MarketData, Trading, Wallet},
+    userstream::{UserDataStream, UserStreamKind},
+    websocket::{BinanceWebsocket, StreamStats},
+    wsapi::BinanceWsApi,
+    Binance,
+};
+pub use crate::config::ClientConfig;
+pub use crate::credentials::{Credentials, Secret};
+pub use crate::middleware::Middleware;
+pub use crate::pool::{Balance, ClientPool};
+pub use crate::response::{Response, ResponseMeta};
+pub use crate::signer::{Ed25519Signer, HmacSigner, RsaSigner, Signer};
+pub use crate::transport::{Priority, RequestOptions};
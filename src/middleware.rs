@@ -0,0 +1,155 @@
+use crate::response::ResponseMeta;
+use failure::Fallible;
+use http::{HeaderMap, Method};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::*;
+use url::Url;
+
+// Hooks run around every REST request. Requests reach `before_request` fully
+// signed, so implementations must not log or store the query string verbatim.
+pub trait Middleware: Send + Sync {
+    // Returning an error aborts the request before it is sent
+    fn before_request(&self, _request: &mut reqwest::Request) -> Fallible<()> {
+        Ok(())
+    }
+
+    // Called once the response headers arrived, before the body is parsed
+    fn after_response(&self, _method: &Method, _url: &Url, _meta: &ResponseMeta) {}
+}
+
+// Lets a middleware be kept around to read its state, e.g. timings
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn before_request(&self, request: &mut reqwest::Request) -> Fallible<()> {
+        (**self).before_request(request)
+    }
+
+    fn after_response(&self, method: &Method, url: &Url, meta: &ResponseMeta) {
+        (**self).after_response(method, url, meta);
+    }
+}
+
+// Logs method, path, status and latency. Query strings are left out as they carry signatures.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingMiddleware;
+
+impl Middleware for LoggingMiddleware {
+    fn before_request(&self, request: &mut reqwest::Request) -> Fallible<()> {
+        debug!("--> {} {}", request.method(), request.url().path());
+        Ok(())
+    }
+
+    fn after_response(&self, method: &Method, url: &Url, meta: &ResponseMeta) {
+        debug!(
+            "<-- {} {} {} in {:?}",
+            method,
+            url.path(),
+            meta.status,
+            meta.latency
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EndpointTiming {
+    pub calls: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+// Latency per endpoint path
+#[derive(Debug, Default)]
+pub struct TimingMiddleware {
+    timings: Mutex<HashMap<String, EndpointTiming>>,
+}
+
+impl TimingMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn timings(&self) -> HashMap<String, EndpointTiming> {
+        self.timings
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Middleware for TimingMiddleware {
+    fn after_response(&self, _method: &Method, url: &Url, meta: &ResponseMeta) {
+        let mut timings = self
+            .timings
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let timing = timings.entry(url.path().to_string()).or_default();
+        timing.calls += 1;
+        timing.total += meta.latency;
+        timing.max = timing.max.max(meta.latency);
+        drop(timings);
+    }
+}
+
+// Adds fixed headers to every request
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMiddleware {
+    headers: HeaderMap,
+}
+
+impl HeaderMiddleware {
+    #[must_use]
+    pub const fn new(headers: HeaderMap) -> Self {
+        Self { headers }
+    }
+}
+
+impl Middleware for HeaderMiddleware {
+    fn before_request(&self, request: &mut reqwest::Request) -> Fallible<()> {
+        for (name, value) in &self.headers {
+            request.headers_mut().insert(name, value.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HeaderMiddleware, Middleware, TimingMiddleware};
+    use crate::response::ResponseMeta;
+    use failure::Fallible;
+    use http::{HeaderMap, HeaderValue, Method, StatusCode};
+    use std::time::Duration;
+    use url::Url;
+
+    #[test]
+    fn builtin_middleware() -> Fallible<()> {
+        let url = Url::parse("https://www.binance.com/api/v3/account?signature=abc")?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-audit-id", HeaderValue::from_static("42"));
+        let mut request = reqwest::Request::new(Method::GET, url.clone());
+        HeaderMiddleware::new(headers).before_request(&mut request)?;
+        assert_eq!(request.headers()["x-audit-id"], "42");
+
+        let timing = TimingMiddleware::new();
+        for latency in &[10, 30] {
+            let meta = ResponseMeta::new(
+                StatusCode::OK,
+                HeaderMap::new(),
+                Duration::from_millis(*latency),
+            );
+            timing.after_response(&Method::GET, &url, &meta);
+        }
+        let timings = timing.timings();
+        let account = &timings["/api/v3/account"];
+        assert_eq!(account.calls, 2);
+        assert_eq!(account.total, Duration::from_millis(40));
+        assert_eq!(account.max, Duration::from_millis(30));
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+// In-process Binance for tests: REST endpoints used by `Binance`, combined
+// streams used by `BinanceWebsocket` and the WebSocket API used by
+// `BinanceWsApi`, backed by a small matching engine.
+//
+// Signed requests are checked against the mock credentials, so signing bugs
+// surface as -1022 just like on the real exchange.
+
+mod rest;
+mod wsapi;
+
+use crate::{
+    client::Binance,
+    config::ClientConfig,
+    credentials::Credentials,
+    engine::{Exchange, Rejection},
+};
+use chrono::Utc;
+use failure::Fallible;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::{self, AbortHandle},
+    prelude::*,
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Server,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::accept_hdr_async;
+use tracing::*;
+use tungstenite::{handshake::server, Message};
+use url::Url;
+
+pub const API_KEY: &str = "mock-api-key";
+pub const API_SECRET: &str = "mock-api-secret";
+
+#[derive(Debug)]
+struct Subscriber {
+    streams: BTreeSet<String>,
+    tx: UnboundedSender<Message>,
+}
+
+#[derive(Debug)]
+struct State {
+    api_key: String,
+    api_secret: String,
+    exchange: Exchange,
+    // Errors returned by the next requests to a path, e.g. "/api/v3/order"
+    failures: HashMap<String, VecDeque<Rejection>>,
+    weight_limit: Option<u64>,
+    used_weight: (i64, u64),
+    subscribers: HashMap<usize, Subscriber>,
+}
+
+impl State {
+    // Adds the weight to the current minute and returns the total
+    fn use_weight(&mut self, weight: u64) -> u64 {
+        let minute = Utc::now().timestamp() / 60;
+        if self.used_weight.0 != minute {
+            self.used_weight = (minute, 0);
+        }
+        self.used_weight.1 += weight;
+        self.used_weight.1
+    }
+
+    // Applies SUBSCRIBE and UNSUBSCRIBE sent over a websocket connection and
+    // returns the reply, frames that fail to parse get an error
+    fn command(&mut self, id: usize, text: &str) -> Value {
+        let command = match serde_json::from_str::<Command>(text) {
+            Ok(command) => command,
+            Err(e) => {
+                let (code, id) = serde_json::from_str::<Value>(text)
+                    .map_or((3, Value::Null), |frame| {
+                        (2, frame.get("id").cloned().unwrap_or(Value::Null))
+                    });
+                return json!({ "code": code, "msg": format!("Invalid request: {}", e), "id": id });
+            }
+        };
+        if let Some(subscriber) = self.subscribers.get_mut(&id) {
+            match command.method.as_str() {
+                "SUBSCRIBE" => subscriber.streams.extend(command.params.iter().cloned()),
+                "UNSUBSCRIBE" => {
+                    for stream in &command.params {
+                        subscriber.streams.remove(stream);
+                    }
+                }
+                _ => {}
+            }
+        }
+        json!({ "result": null, "id": command.id })
+    }
+
+    // Sends pending exchange events to subscribers of their stream
+    fn flush(&mut self) {
+        for (stream, data) in self.exchange.events.drain(..) {
+            let frame = json!({ "stream": stream, "data": data }).to_string();
+            self.subscribers.retain(|_, s| {
+                !s.streams.contains(&stream)
+                    || s.tx.unbounded_send(Message::Text(frame.clone())).is_ok()
+            });
+        }
+    }
+}
+
+pub struct MockServer {
+    state: Arc<Mutex<State>>,
+    rest_addr: SocketAddr,
+    ws_addr: SocketAddr,
+    tasks: Vec<AbortHandle>,
+}
+
+impl MockServer {
+    // Binds REST and websocket listeners on random local ports
+    pub async fn start() -> Fallible<Self> {
+        let state = Arc::new(Mutex::new(State {
+            api_key: API_KEY.to_string(),
+            api_secret: API_SECRET.to_string(),
+            exchange: Exchange::default(),
+            failures: HashMap::new(),
+            weight_limit: None,
+            used_weight: (0, 0),
+            subscribers: HashMap::new(),
+        }));
+
+        let rest_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = rest_state.clone();
+            future::ok::<_, Infallible>(service_fn(move |request| {
+                rest::handle(state.clone(), request)
+            }))
+        });
+        let rest = Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make_service);
+        let rest_addr = rest.local_addr();
+
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let ws_addr = listener.local_addr()?;
+        let ws_state = state.clone();
+        let websocket = async move {
+            let mut next_id = 0;
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        next_id += 1;
+                        tokio::spawn(serve_socket(ws_state.clone(), next_id, stream));
+                    }
+                    Err(e) => warn!("[Mock] Accept failed: {}", e),
+                }
+            }
+        };
+
+        let mut tasks = Vec::new();
+        let (rest, handle) = future::abortable(rest);
+        tasks.push(handle);
+        tokio::spawn(rest.map(|_| ()));
+        let (websocket, handle) = future::abortable(websocket);
+        tasks.push(handle);
+        tokio::spawn(websocket.map(|_| ()));
+
+        Ok(Self {
+            state,
+            rest_addr,
+            ws_addr,
+            tasks,
+        })
+    }
+
+    // Endpoint overrides pointing at this server
+    #[must_use]
+    pub fn config(&self) -> ClientConfig {
+        let url = |s: String| Url::parse(&s).ok();
+        ClientConfig {
+            rest_url: url(format!("http://{}", self.rest_addr)),
+            stream_url: url(format!("ws://{}/stream", self.ws_addr)),
+            ws_api_url: url(format!("ws://{}/ws-api/v3", self.ws_addr)),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[must_use]
+    pub fn credentials(&self) -> Credentials {
+        Credentials::new(API_KEY, API_SECRET)
+    }
+
+    // Client with the mock credentials, talking to this server
+    pub fn binance(&self) -> Fallible<Binance> {
+        Binance::with_credentials(self.credentials()).with_config(self.config())
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn add_symbol(&self, symbol: &str, base: &str, quote: &str, price: f64) {
+        self.state().exchange.add_symbol(symbol, base, quote, price);
+    }
+
+    pub fn set_balance(&self, asset: &str, free: f64) {
+        self.state().exchange.set_balance(asset, free);
+    }
+
+    // Moves the market price, filling resting orders it crosses
+    pub fn set_price(&self, symbol: &str, price: f64) {
+        let mut state = self.state();
+        state.exchange.set_price(symbol, price);
+        state.flush();
+    }
+
+    pub fn set_depth(&self, symbol: &str, bids: &[(f64, f64)], asks: &[(f64, f64)]) {
+        self.state().exchange.set_depth(symbol, bids, asks);
+    }
+
+    // Next request to `path` fails with the given Binance error. WebSocket API
+    // requests are failed by method, e.g. "order.place".
+    pub fn fail_next(&self, path: &str, status: u16, code: i64, msg: &str) {
+        self.state()
+            .failures
+            .entry(rest::normalize(path).to_string())
+            .or_default()
+            .push_back((status, code, msg.to_string()));
+    }
+
+    // Requests past this weight per minute get 429 and -1003
+    pub fn set_weight_limit(&self, limit: Option<u64>) {
+        self.state().weight_limit = limit;
+    }
+
+    // Closes every websocket connection as if the server went away
+    pub fn drop_connections(&self) {
+        for subscriber in self.state().subscribers.values() {
+            let _ = subscriber.tx.unbounded_send(Message::Close(None));
+        }
+    }
+
+    // Sends a scripted event to subscribers of the stream, e.g. "bnbbtc@depth"
+    pub fn publish(&self, stream: &str, data: Value) {
+        let mut state = self.state();
+        state.exchange.events.push((stream.to_string(), data));
+        state.flush();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Command {
+    method: String,
+    #[serde(default)]
+    params: Vec<String>,
+    id: u64,
+}
+
+async fn serve_socket(state: Arc<Mutex<State>>, id: usize, stream: TcpStream) {
+    let mut streams = BTreeSet::new();
+    let mut ws_api = false;
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &server::Request,
+                    response: server::Response|
+     -> Result<server::Response, server::ErrorResponse> {
+        ws_api = request.uri().path() == "/ws-api/v3";
+        let query = request.uri().query().unwrap_or("");
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            if key == "streams" {
+                streams.extend(value.split('/').map(String::from));
+            }
+        }
+        Ok(response)
+    };
+    let socket = match accept_hdr_async(stream, callback).await {
+        Ok(socket) => socket,
+        Err(e) => return warn!("[Mock] Websocket handshake failed: {}", e),
+    };
+
+    let (tx, rx) = unbounded();
+    let lock = || {
+        state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    };
+    lock().subscribers.insert(
+        id,
+        Subscriber {
+            streams,
+            tx: tx.clone(),
+        },
+    );
+
+    let (sink, mut incoming) = socket.split();
+    let writer = rx.map(Ok).forward(sink);
+    let reader = async {
+        while let Some(Ok(msg)) = incoming.next().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let reply = if ws_api {
+                wsapi::handle(&mut lock(), &text)
+            } else {
+                lock().command(id, &text)
+            };
+            if tx.unbounded_send(Message::Text(reply.to_string())).is_err() {
+                break;
+            }
+        }
+    };
+
+    future::select(writer.boxed(), reader.boxed()).await;
+    lock().subscribers.remove(&id);
+}
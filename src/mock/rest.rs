@@ -0,0 +1,223 @@
+use super::State;
+use crate::{
+    engine::{reject, Rejection},
+    signer::{HmacSigner, Signer},
+};
+use chrono::Utc;
+use hyper::{body::to_bytes, Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+// Request weight as charged by Binance, roughly
+fn weight(path: &str) -> u64 {
+    match path {
+        "/account" | "/exchangeInfo" | "/myTrades" | "/ticker/24hr" => 10,
+        "/depth" | "/openOrders" => 5,
+        "/ticker/allPrices" | "/ticker/allBookTickers" => 2,
+        _ => 1,
+    }
+}
+
+pub(super) async fn handle(
+    state: Arc<Mutex<State>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body).await?;
+
+    let mut state = state
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let path = parts.uri.path().to_string();
+    let used_weight = state.use_weight(weight(normalize(&path)));
+
+    let result = match state
+        .failures
+        .get_mut(normalize(&path))
+        .and_then(VecDeque::pop_front)
+    {
+        Some(failure) => Err(failure),
+        None => match state.weight_limit {
+            Some(limit) if used_weight > limit => Err((
+                429,
+                -1003,
+                format!(
+                    "Too many requests; current limit is {} request weight per 1 MINUTE.",
+                    limit
+                ),
+            )),
+            _ => route(
+                &mut state,
+                &parts.method,
+                &path,
+                parts.uri.query().unwrap_or(""),
+                parts
+                    .headers
+                    .get("X-MBX-APIKEY")
+                    .and_then(|key| key.to_str().ok()),
+                &body,
+            ),
+        },
+    };
+    state.flush();
+    drop(state);
+
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err((status, code, msg)) => (status, json!({ "code": code, "msg": msg })),
+    };
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST))
+        .header("Content-Type", "application/json")
+        .header("X-MBX-USED-WEIGHT-1M", used_weight.to_string())
+        .body(Body::from(body.to_string()))
+        .unwrap_or_default())
+}
+
+pub(super) fn unsupported() -> Rejection {
+    (404, -1020, "This operation is not supported.".to_string())
+}
+
+// Both API versions are served alike
+pub(super) fn normalize(path: &str) -> &str {
+    path.strip_prefix("/api/v1")
+        .or_else(|| path.strip_prefix("/api/v3"))
+        .unwrap_or(path)
+}
+
+fn route(
+    state: &mut State,
+    method: &Method,
+    path: &str,
+    query: &str,
+    api_key: Option<&str>,
+    body: &[u8],
+) -> Result<Value, Rejection> {
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .chain(url::form_urlencoded::parse(body))
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let exchange = &mut state.exchange;
+
+    match (method, normalize(path)) {
+        (&Method::GET, "/ping") => return Ok(json!({})),
+        (&Method::GET, "/time") => {
+            return Ok(json!({ "serverTime": Utc::now().timestamp_millis() }))
+        }
+        (&Method::GET, "/exchangeInfo") => return Ok(exchange.exchange_info()),
+        (&Method::GET, "/depth") => return exchange.depth(&params),
+        (&Method::GET, "/ticker/allPrices") => return Ok(exchange.prices()),
+        (&Method::GET, "/ticker/allBookTickers") => return Ok(exchange.book_tickers()),
+        _ => {}
+    }
+
+    // Everything else needs an API key, user data streams go unsigned
+    if api_key != Some(state.api_key.as_str()) {
+        return Err(reject(
+            -2015,
+            "Invalid API-key, IP, or permissions for action.",
+        ));
+    }
+    let exchange = &mut state.exchange;
+    if path.ends_with("/userDataStream") || path.ends_with("/userDataStream/isolated") {
+        return match *method {
+            Method::POST => Ok(exchange.create_listen_key()),
+            Method::PUT => exchange.listen_key(&params, false),
+            Method::DELETE => exchange.listen_key(&params, true),
+            _ => Err(unsupported()),
+        };
+    }
+
+    verify(&state.api_secret, query, body, &params)?;
+    let exchange = &mut state.exchange;
+    match (method, normalize(path)) {
+        (&Method::GET, "/account") => Ok(exchange.account()),
+        (&Method::GET, "/openOrders") => Ok(exchange.open_orders(&params)),
+        (&Method::GET, "/myTrades") => exchange.trades(&params),
+        (&Method::GET, "/order") => exchange.order(&params),
+        (&Method::POST, "/order") => exchange.place(&params),
+        (&Method::DELETE, "/order") => exchange.cancel(&params),
+        _ => Err(unsupported()),
+    }
+}
+
+// Checks the HMAC signature over the query and body, and the timestamp against recvWindow
+fn verify(
+    secret: &str,
+    query: &str,
+    body: &[u8],
+    params: &HashMap<String, String>,
+) -> Result<(), Rejection> {
+    let invalid = || reject(-1022, "Signature for this request is not valid.");
+    let unsigned = query
+        .rfind("signature=")
+        .map(|idx| query[..idx].trim_end_matches('&'))
+        .ok_or_else(invalid)?;
+    let payload = [unsigned.as_bytes(), body].concat();
+    let expected = HmacSigner::new("", secret)
+        .sign(&payload)
+        .map_err(|_| invalid())?;
+    if params.get("signature") != Some(&expected) {
+        return Err(invalid());
+    }
+    check_timestamp(params)
+}
+
+pub(super) fn check_timestamp(params: &HashMap<String, String>) -> Result<(), Rejection> {
+    let timestamp: i64 = params
+        .get("timestamp")
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| reject(-1102, "Mandatory parameter 'timestamp' was not sent."))?;
+    let recv_window: i64 = params
+        .get("recvWindow")
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(5000);
+    let now = Utc::now().timestamp_millis();
+    if timestamp > now + 1000 || now - timestamp > recv_window {
+        return Err(reject(
+            -1021,
+            "Timestamp for this request is outside of the recvWindow.",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify;
+    use crate::signer::{HmacSigner, Signer};
+    use chrono::Utc;
+    use failure::Fallible;
+    use std::collections::HashMap;
+
+    #[test]
+    fn signature_check() -> Fallible<()> {
+        let query = format!(
+            "symbol=BNBBTC&timestamp={}&recvWindow=5000",
+            Utc::now().timestamp_millis()
+        );
+        let body = b"side=BUY";
+        let signature =
+            HmacSigner::new("key", "secret").sign(&[query.as_bytes(), body].concat())?;
+        let signed = format!("{}&signature={}", query, signature);
+        let params: HashMap<_, _> = url::form_urlencoded::parse(signed.as_bytes())
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        assert!(verify("secret", &signed, body, &params).is_ok());
+        assert_eq!(
+            verify("other", &signed, body, &params).unwrap_err().1,
+            -1022
+        );
+        assert_eq!(
+            verify("secret", &signed, b"side=SELL", &params)
+                .unwrap_err()
+                .1,
+            -1022
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use super::{rest, State};
+use crate::{
+    engine::{reject, Exchange, Rejection},
+    signer::{HmacSigner, Signer},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{from_str, json, Map, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+#[derive(Deserialize)]
+struct Request {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Map<String, Value>,
+}
+
+// Answers a WebSocket API frame, frames that fail to parse get an error
+// carrying their id if they have one
+pub(super) fn handle(state: &mut State, text: &str) -> Value {
+    let (id, result) = match from_str::<Request>(text) {
+        Ok(request) => {
+            let result = route(state, &request.method, &request.params);
+            (request.id, result)
+        }
+        Err(e) => {
+            let id = from_str::<Value>(text)
+                .ok()
+                .and_then(|frame| frame.get("id").cloned())
+                .unwrap_or(Value::Null);
+            (id, Err(reject(-1102, &format!("Malformed request: {}", e))))
+        }
+    };
+    state.flush();
+
+    match result {
+        Ok(result) => json!({ "id": id, "status": 200, "result": result }),
+        Err((status, code, msg)) => json!({
+            "id": id,
+            "status": status,
+            "error": { "code": code, "msg": msg },
+        }),
+    }
+}
+
+fn route(state: &mut State, method: &str, params: &Map<String, Value>) -> Result<Value, Rejection> {
+    if let Some(failure) = state.failures.get_mut(method).and_then(VecDeque::pop_front) {
+        return Err(failure);
+    }
+
+    let params: HashMap<String, String> =
+        params.iter().map(|(k, v)| (k.clone(), param(v))).collect();
+    let exchange = &mut state.exchange;
+    match method {
+        "ping" => return Ok(json!({})),
+        "time" => return Ok(json!({ "serverTime": Utc::now().timestamp_millis() })),
+        "depth" => return exchange.depth(&params),
+        "ticker.price" => return price(exchange, &params),
+        _ => {}
+    }
+
+    verify(&state.api_key, &state.api_secret, &params)?;
+    let exchange = &mut state.exchange;
+    match method {
+        "account.status" => Ok(exchange.account()),
+        "openOrders.status" => Ok(exchange.open_orders(&params)),
+        "order.status" => exchange.order(&params),
+        "order.place" => exchange.place(&params),
+        "order.cancel" => exchange.cancel(&params),
+        // session.logon needs an Ed25519 key, which the mock does not have
+        _ => Err(rest::unsupported()),
+    }
+}
+
+fn param(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn price(exchange: &Exchange, params: &HashMap<String, String>) -> Result<Value, Rejection> {
+    let symbol = params
+        .get("symbol")
+        .ok_or_else(|| reject(-1102, "Mandatory parameter 'symbol' was not sent."))?;
+    match exchange.prices() {
+        Value::Array(prices) => prices
+            .into_iter()
+            .find(|price| price["symbol"] == *symbol)
+            .ok_or_else(|| reject(-1121, "Invalid symbol.")),
+        _ => Err(reject(-1121, "Invalid symbol.")),
+    }
+}
+
+// Checks the api key and the HMAC signature over the sorted parameters
+fn verify(api_key: &str, secret: &str, params: &HashMap<String, String>) -> Result<(), Rejection> {
+    if params.get("apiKey").map(String::as_str) != Some(api_key) {
+        return Err(reject(
+            -2015,
+            "Invalid API-key, IP, or permissions for action.",
+        ));
+    }
+
+    let invalid = || reject(-1022, "Signature for this request is not valid.");
+    let payload = params
+        .iter()
+        .filter(|(k, _)| *k != "signature")
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+    let expected = HmacSigner::new("", secret)
+        .sign(payload.as_bytes())
+        .map_err(|_| invalid())?;
+    if params.get("signature") != Some(&expected) {
+        return Err(invalid());
+    }
+    rest::check_timestamp(params)
+}
@@ -86,8 +86,8 @@ pub struct Bids {
     #[serde(with = "string_or_float")]
     pub qty: f64,
 
-    // Never serialized.
-    #[serde(skip_serializing)]
+    // Never serialized, absent from current API payloads.
+    #[serde(default, skip_serializing)]
     ignore: Vec<String>,
 }
 
@@ -98,8 +98,8 @@ pub struct Asks {
     #[serde(with = "string_or_float")]
     pub qty: f64,
 
-    // Never serialized.
-    #[serde(skip_serializing)]
+    // Never serialized, absent from current API payloads.
+    #[serde(default, skip_serializing)]
     ignore: Vec<String>,
 }
 
@@ -109,7 +109,9 @@ pub struct UserDataStream {
     pub listen_key: String,
 }
 
+// Empty acknowledgement, error bodies must not parse as one
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Success {}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -241,9 +243,9 @@ pub struct Kline {
     #[serde(rename = "i")]
     pub interval: String,
     #[serde(rename = "f")]
-    pub first_trade_id: i32,
+    pub first_trade_id: i64, // -1 without trades
     #[serde(rename = "L")]
-    pub last_trade_id: i32,
+    pub last_trade_id: i64,
     #[serde(rename = "o")]
     pub open: String,
     #[serde(rename = "c")]
@@ -255,7 +257,7 @@ pub struct Kline {
     #[serde(rename = "v")]
     pub volume: String,
     #[serde(rename = "n")]
-    pub number_of_trades: i32,
+    pub number_of_trades: i64,
     #[serde(rename = "x")]
     pub is_final_bar: bool,
     #[serde(rename = "q")]
@@ -264,7 +266,7 @@ pub struct Kline {
     pub active_buy_volume: String,
     #[serde(rename = "Q")]
     pub active_volume_buy_quote: String,
-    #[serde(skip_serializing, rename = "B")]
+    #[serde(default, skip_serializing, rename = "B")]
     pub ignore_me: String,
 }
 //  "timezone": "UTC",
@@ -340,6 +342,7 @@ pub struct RateLimit {
 pub enum RateLimitType {
     Orders,
     RequestWeight,
+    RawRequests,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -376,9 +379,24 @@ pub enum SymbolFilter {
     #[serde(rename_all = "camelCase")]
     MaxNumAlgoOrders { max_num_algo_orders: u64 },
     #[serde(rename_all = "camelCase")]
-    MaxNumOrders { limit: u64 },
+    MaxNumOrders { max_num_orders: u64 },
     #[serde(rename_all = "camelCase")]
     IcebergParts { limit: u64 },
+    #[serde(rename_all = "camelCase")]
+    MarketLotSize {
+        min_qty: String,
+        max_qty: String,
+        step_size: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    PercentPrice {
+        multiplier_up: String,
+        multiplier_down: String,
+        avg_price_mins: u64,
+    },
+    // Filters added to the API after this list was written
+    #[serde(other)]
+    Other,
 }
 
 // {
@@ -408,8 +426,12 @@ pub enum SymbolFilter {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ExchangeFilter {
-    ExchangeMaxNumOrders { limit: u64 },
-    ExchangeMaxAlgoOrders { limit: u64 },
+    #[serde(rename_all = "camelCase")]
+    ExchangeMaxNumOrders { max_num_orders: u64 },
+    #[serde(rename_all = "camelCase")]
+    ExchangeMaxNumAlgoOrders { max_num_algo_orders: u64 },
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -454,7 +476,6 @@ pub enum OrderType {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum TimeInForce {
     GTC,
     IOC,
@@ -465,6 +486,15 @@ pub enum TimeInForce {
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderExecType {
     New,
+    Canceled,
+    Replaced,
+    Rejected,
+    Trade,
+    Expired,
+    TradePrevention,
+    // Execution types added to the API later, e.g. AMENDMENT
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -477,12 +507,28 @@ pub enum OrderStatus {
     PendingCancel,
     Rejected,
     Expired,
+    ExpiredInMatch,
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderRejectReason {
     None,
+    UnknownInstrument,
+    MarketClosed,
+    PriceQtyExceedHardLimits,
+    UnknownOrder,
+    DuplicateOrder,
+    UnknownAccount,
+    InsufficientBalance,
+    AccountInactive,
+    AccountCannotSettle,
+    OrderWouldTriggerImmediately,
+    // Reasons added to the API after this list was written
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
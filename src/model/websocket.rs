@@ -2,9 +2,10 @@ use super::{
     string_or_float, Asks, Bids, Kline, OrderBook, OrderExecType, OrderRejectReason, OrderStatus,
     OrderType, Side, TimeInForce,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum Subscription {
     UserData(String),            // listen key
     AggregateTrade(String),      //symbol
@@ -14,15 +15,82 @@ pub enum Subscription {
     MiniTickerAll,
     Ticker(String), // symbol
     TickerAll,
-    OrderBook(String, i64), //symbol, depth
-    Depth(String),          //symbol
+    OrderBook(String, DepthLevel, UpdateSpeed), //symbol, levels, speed
+    Depth(String, UpdateSpeed),                 //symbol, speed
+    BookTicker(String),                         //symbol
+    BookTickerAll,
+}
+
+// Levels of a partial book depth stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum DepthLevel {
+    Five,
+    Ten,
+    Twenty,
+}
+
+impl DepthLevel {
+    #[must_use]
+    pub const fn levels(self) -> u32 {
+        match self {
+            Self::Five => 5,
+            Self::Ten => 10,
+            Self::Twenty => 20,
+        }
+    }
+}
+
+// Push interval of depth streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum UpdateSpeed {
+    Millis100,
+    Millis1000,
+}
+
+impl UpdateSpeed {
+    // Stream name suffix, 1000ms is the default and has none
+    const fn suffix(self) -> &'static str {
+        match self {
+            Self::Millis100 => "@100ms",
+            Self::Millis1000 => "",
+        }
+    }
+}
+
+impl Subscription {
+    // Stream name as used in combined stream urls and envelopes
+    #[must_use]
+    pub fn stream_name(&self) -> String {
+        match self {
+            Self::AggregateTrade(symbol) => format!("{}@aggTrade", symbol.to_lowercase()),
+            Self::BookTicker(symbol) => format!("{}@bookTicker", symbol.to_lowercase()),
+            Self::BookTickerAll => "!bookTicker".to_string(),
+            Self::Candlestick(symbol, interval) => {
+                format!("{}@kline_{}", symbol.to_lowercase(), interval)
+            }
+            Self::Depth(symbol, speed) => {
+                format!("{}@depth{}", symbol.to_lowercase(), speed.suffix())
+            }
+            Self::MiniTicker(symbol) => format!("{}@miniTicker", symbol.to_lowercase()),
+            Self::MiniTickerAll => "!miniTicker@arr".to_string(),
+            Self::OrderBook(symbol, level, speed) => format!(
+                "{}@depth{}{}",
+                symbol.to_lowercase(),
+                level.levels(),
+                speed.suffix()
+            ),
+            Self::Ticker(symbol) => format!("{}@ticker", symbol.to_lowercase()),
+            Self::TickerAll => "!ticker@arr".to_string(),
+            Self::Trade(symbol) => format!("{}@trade", symbol.to_lowercase()),
+            Self::UserData(key) => key.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum BinanceWebsocketMessage {
-    UserOrderUpdate(UserOrderUpdate),
-    UserAccountUpdate(AccountUpdate),
+    UserData(UserDataEvent),
     AggregateTrade(AggregateTrade),
     Trade(TradeMessage),
     Candlestick(CandelStickMessage),
@@ -32,6 +100,10 @@ pub enum BinanceWebsocketMessage {
     TickerAll(Vec<Ticker>),
     OrderBook(OrderBook),
     Depth(Depth),
+    BookTicker(BookTickerUpdate),
+    Disconnected(Vec<Subscription>), // reconnection in progress
+    Reconnected(Vec<Subscription>),  // resubscribed
+    Stale(Subscription),             // no data within the idle timeout
     Ping,
     Pong,
     Binary(Vec<u8>), // Unexpected, unparsed
@@ -60,7 +132,7 @@ pub struct TradeMessage {
     pub trade_order_time: u64,
     #[serde(rename = "m")]
     pub is_buyer_maker: bool,
-    #[serde(skip_serializing, rename = "M")]
+    #[serde(default, skip_serializing, rename = "M")]
     pub m_ignore: bool,
 }
 
@@ -87,10 +159,49 @@ pub struct AggregateTrade {
     pub trade_order_time: u64,
     #[serde(rename = "m")]
     pub is_buyer_maker: bool,
-    #[serde(skip_serializing, rename = "M")]
+    #[serde(default, skip_serializing, rename = "M")]
     pub m_ignore: bool,
 }
 
+// User data stream event, dispatched on the event type
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum UserDataEvent {
+    ExecutionReport(UserOrderUpdate),
+    AccountInfo(AccountUpdate), // legacy outboundAccountInfo
+    AccountPosition(AccountPositionUpdate),
+    BalanceUpdate(BalanceUpdate),
+    ListStatus(ListStatus),
+    ListenKeyExpired(ListenKeyExpired),
+    Unknown(Value),
+}
+
+impl<'de> Deserialize<'de> for UserDataEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let event = match value.get("e").and_then(Value::as_str) {
+            Some("executionReport") => Self::ExecutionReport(from_value(value)?),
+            Some("outboundAccountInfo") => Self::AccountInfo(from_value(value)?),
+            Some("outboundAccountPosition") => Self::AccountPosition(from_value(value)?),
+            Some("balanceUpdate") => Self::BalanceUpdate(from_value(value)?),
+            Some("listStatus") => Self::ListStatus(from_value(value)?),
+            Some("listenKeyExpired") => Self::ListenKeyExpired(from_value(value)?),
+            _ => return Ok(Self::Unknown(value)),
+        };
+        Ok(event)
+    }
+}
+
+// Deserializes a known event, failing the whole event on a schema mismatch
+fn from_value<T, E>(value: Value) -> Result<T, E>
+where
+    T: for<'a> Deserialize<'a>,
+    E: de::Error,
+{
+    serde_json::from_value(value).map_err(E::custom)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct UserOrderUpdate {
@@ -116,10 +227,10 @@ pub struct UserOrderUpdate {
     pub stop_price: f64,
     #[serde(rename = "F", with = "string_or_float")]
     pub iceberg_qty: f64,
-    #[serde(skip_serializing)]
-    pub g: i32,
-    #[serde(skip_serializing, rename = "C")]
-    pub c_ignore: Option<String>,
+    #[serde(rename = "g")]
+    pub order_list_id: i64,
+    #[serde(rename = "C")]
+    pub original_client_order_id: Option<String>, // set for cancels
     #[serde(rename = "x")]
     pub execution_type: OrderExecType,
     #[serde(rename = "X")]
@@ -136,24 +247,104 @@ pub struct UserOrderUpdate {
     pub price_last_filled_trade: f64,
     #[serde(rename = "n", with = "string_or_float")]
     pub commission: f64,
-    #[serde(skip_serializing, rename = "N")]
+    #[serde(rename = "N")]
     pub asset_commisioned: Option<String>,
     #[serde(rename = "T")]
     pub trade_order_time: u64,
     #[serde(rename = "t")]
-    pub trade_id: i64,
-    #[serde(skip_serializing, rename = "I")]
+    pub trade_id: i64, // -1 unless the execution is a trade
+    #[serde(default, skip_serializing, rename = "I")]
     pub i_ignore: u64,
-    #[serde(skip_serializing)]
-    pub w: bool,
+    #[serde(rename = "w")]
+    pub is_working: bool,
     #[serde(rename = "m")]
     pub is_buyer_maker: bool,
-    #[serde(skip_serializing, rename = "M")]
+    #[serde(default, skip_serializing, rename = "M")]
     pub m_ignore: bool,
-    #[serde(skip_serializing, rename = "O")]
+    #[serde(rename = "O")]
     pub order_creation_time: u64,
-    #[serde(skip_serializing, rename = "Z", with = "string_or_float")]
+    #[serde(rename = "Z", with = "string_or_float")]
     pub cumulative_quote_asset_transacted_qty: f64,
+    #[serde(rename = "Y", with = "string_or_float")]
+    pub last_quote_asset_transacted_qty: f64,
+    #[serde(rename = "Q", with = "string_or_float")]
+    pub quote_order_qty: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountPositionUpdate {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "u")]
+    pub last_account_update: u64,
+    #[serde(rename = "B")]
+    pub balances: Vec<AccountUpdateBalance>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceUpdate {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "a")]
+    pub asset: String,
+    #[serde(rename = "d", with = "string_or_float")]
+    pub balance_delta: f64,
+    #[serde(rename = "T")]
+    pub clear_time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListStatus {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "g")]
+    pub order_list_id: i64,
+    #[serde(rename = "c")]
+    pub contingency_type: String,
+    #[serde(rename = "l")]
+    pub list_status_type: String,
+    #[serde(rename = "L")]
+    pub list_order_status: String,
+    #[serde(rename = "r")]
+    pub list_reject_reason: String,
+    #[serde(rename = "C")]
+    pub list_client_order_id: String,
+    #[serde(rename = "T")]
+    pub transaction_time: u64,
+    #[serde(rename = "O")]
+    pub orders: Vec<ListStatusOrder>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListStatusOrder {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "i")]
+    pub order_id: u64,
+    #[serde(rename = "c")]
+    pub client_order_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenKeyExpired {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    pub listen_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -226,6 +417,23 @@ pub struct Ticker {
     pub num_trades: u64,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BookTickerUpdate {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b", with = "string_or_float")]
+    pub best_bid: f64,
+    #[serde(rename = "B", with = "string_or_float")]
+    pub best_bid_qty: f64,
+    #[serde(rename = "a", with = "string_or_float")]
+    pub best_ask: f64,
+    #[serde(rename = "A", with = "string_or_float")]
+    pub best_ask_qty: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct CandelStickMessage {
@@ -246,14 +454,14 @@ pub struct AccountUpdate {
     pub event_type: String,
     #[serde(rename = "E")]
     pub event_time: u64,
-    #[serde(rename = "m")]
-    pub maker_commision_rate: u64,
-    #[serde(rename = "t")]
-    pub taker_commision_rate: u64,
-    #[serde(rename = "b")]
-    pub buyer_commision_rate: u64,
-    #[serde(rename = "s")]
-    pub seller_commision_rate: u64,
+    #[serde(rename = "m", with = "string_or_float")]
+    pub maker_commision_rate: f64,
+    #[serde(rename = "t", with = "string_or_float")]
+    pub taker_commision_rate: f64,
+    #[serde(rename = "b", with = "string_or_float")]
+    pub buyer_commision_rate: f64,
+    #[serde(rename = "s", with = "string_or_float")]
+    pub seller_commision_rate: f64,
     #[serde(rename = "T")]
     pub can_trade: bool,
     #[serde(rename = "W")]
@@ -299,3 +507,68 @@ pub struct MiniTicker {
     #[serde(rename = "q", with = "string_or_float")]
     pub quote_volume: f64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::UserDataEvent;
+    use crate::model::{OrderExecType, OrderRejectReason, OrderStatus};
+    use failure::Fallible;
+    use serde_json::{from_value, json};
+
+    #[test]
+    fn user_data_dispatch() -> Fallible<()> {
+        let trade = json!({
+            "e": "executionReport", "E": 1_499_405_658_658_u64, "s": "ETHBTC",
+            "c": "mUvoqJxFIILMdfAW5iGSOW", "S": "BUY", "o": "LIMIT", "f": "GTC",
+            "q": "1.00000000", "p": "0.10264410", "P": "0.00000000", "F": "0.00000000",
+            "g": -1, "C": "", "x": "TRADE", "X": "PARTIALLY_FILLED", "r": "NONE",
+            "i": 4_293_153, "l": "0.50000000", "z": "0.50000000", "L": "0.10264410",
+            "n": "0.00005132", "N": "BNB", "T": 1_499_405_658_657_u64, "t": 77,
+            "I": 8_641_984, "w": false, "m": true, "M": false,
+            "O": 1_499_405_658_657_u64, "Z": "0.05132205", "Y": "0.05132205", "Q": "0.00000000"
+        });
+        match from_value(trade)? {
+            UserDataEvent::ExecutionReport(report) => {
+                assert!(matches!(report.execution_type, OrderExecType::Trade));
+                assert!(matches!(report.order_status, OrderStatus::PartiallyFilled));
+                assert_eq!(report.order_list_id, -1);
+                assert_eq!(report.trade_id, 77);
+            }
+            _ => panic!("expected an execution report"),
+        }
+
+        let rejected: OrderRejectReason = from_value(json!("SOME_NEW_REASON"))?;
+        assert!(matches!(rejected, OrderRejectReason::Other));
+        let amended: OrderExecType = from_value(json!("AMENDMENT"))?;
+        assert!(matches!(amended, OrderExecType::Other));
+
+        let balance = json!({
+            "e": "balanceUpdate", "E": 1_573_200_697_110_u64, "a": "BTC",
+            "d": "100.00000000", "T": 1_573_200_697_068_u64
+        });
+        assert!(matches!(
+            from_value(balance)?,
+            UserDataEvent::BalanceUpdate(update) if update.asset == "BTC"
+        ));
+
+        let position = json!({
+            "e": "outboundAccountPosition", "E": 1_564_034_571_105_u64, "u": 1_564_034_571_073_u64,
+            "B": [{"a": "ETH", "f": "10000.000000", "l": "0.000000"}]
+        });
+        assert!(matches!(
+            from_value(position)?,
+            UserDataEvent::AccountPosition(update) if update.balances.len() == 1
+        ));
+
+        let expired =
+            json!({"e": "listenKeyExpired", "E": 1_576_653_824_250_u64, "listenKey": "abc"});
+        assert!(matches!(
+            from_value(expired)?,
+            UserDataEvent::ListenKeyExpired(_)
+        ));
+
+        let unknown = json!({"e": "externalLockUpdate", "E": 1});
+        assert!(matches!(from_value(unknown)?, UserDataEvent::Unknown(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{config::ClientConfig, response::ResponseMeta, transport::Priority};
+use chrono::Utc;
+use failure::Fallible;
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+// How requests are spread over the clients of a pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    RoundRobin,
+    // Client with the lowest used weight in the current minute
+    Headroom,
+}
+
+// Used weight as reported for a one minute window
+#[derive(Debug, Clone, Copy, Default)]
+struct UsedWeight {
+    minute: i64,
+    weight: u64,
+}
+
+#[derive(Debug)]
+struct Member {
+    client: reqwest::Client,
+    local_address: Option<IpAddr>,
+    used: Mutex<UsedWeight>,
+}
+
+impl Member {
+    fn used_weight(&self, minute: i64) -> u64 {
+        let used = *self
+            .used
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if used.minute == minute {
+            used.weight
+        } else {
+            0
+        }
+    }
+}
+
+// Several clients, usually bound to different egress IPs since Binance
+// rate limits per IP
+#[derive(Debug)]
+pub struct ClientPool {
+    members: Vec<Member>,
+    balance: Balance,
+    next: AtomicUsize,
+}
+
+impl ClientPool {
+    pub(crate) fn single(client: reqwest::Client, local_address: Option<IpAddr>) -> Self {
+        Self {
+            members: vec![Member {
+                client,
+                local_address,
+                used: Mutex::default(),
+            }],
+            balance: Balance::RoundRobin,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Clients along with the local address they are bound to, if any
+    pub fn from_clients<I>(clients: I, balance: Balance) -> Fallible<Self>
+    where
+        I: IntoIterator<Item = (reqwest::Client, Option<IpAddr>)>,
+    {
+        let members: Vec<_> = clients
+            .into_iter()
+            .map(|(client, local_address)| Member {
+                client,
+                local_address,
+                used: Mutex::default(),
+            })
+            .collect();
+        if members.is_empty() {
+            return Err(failure::format_err!(
+                "Client pool needs at least one client"
+            ));
+        }
+
+        Ok(Self {
+            members,
+            balance,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    // One client per address, otherwise configured alike
+    pub fn from_local_addresses(
+        config: &ClientConfig,
+        addresses: &[IpAddr],
+        balance: Balance,
+    ) -> Fallible<Self> {
+        let clients = addresses
+            .iter()
+            .map(|&address| {
+                let config = ClientConfig {
+                    local_address: Some(address),
+                    ..config.clone()
+                };
+                Ok((config.http_client()?, Some(address)))
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+
+        Self::from_clients(clients, balance)
+    }
+
+    // Used weight in the current minute per local address
+    #[must_use]
+    pub fn used_weights(&self) -> Vec<(Option<IpAddr>, u64)> {
+        let minute = current_minute();
+        self.members
+            .iter()
+            .map(|m| (m.local_address, m.used_weight(minute)))
+            .collect()
+    }
+
+    // High priority requests always go to the client with the most headroom,
+    // low priority ones leave it to the others. With a single client the
+    // priority makes no difference.
+    pub(crate) fn pick(&self, priority: Priority) -> usize {
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % self.members.len();
+        let minute = current_minute();
+        let least_used = self.least_used(start, minute, None).unwrap_or(start);
+        match (priority, self.balance) {
+            (Priority::High, _) | (Priority::Normal, Balance::Headroom) => least_used,
+            (Priority::Normal, Balance::RoundRobin) => start,
+            (Priority::Low, _) => self
+                .least_used(start, minute, Some(least_used))
+                .unwrap_or(least_used),
+        }
+    }
+
+    // Ties go round-robin, starting after the last pick
+    fn least_used(&self, start: usize, minute: i64, skip: Option<usize>) -> Option<usize> {
+        (0..self.members.len())
+            .map(|offset| (start + offset) % self.members.len())
+            .filter(|&idx| Some(idx) != skip)
+            .min_by_key(|&idx| self.members[idx].used_weight(minute))
+    }
+
+    pub(crate) fn client(&self, idx: usize) -> &reqwest::Client {
+        &self.members[idx].client
+    }
+
+    // Local address of the client, its index when it is not bound to one
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn label(&self, idx: usize) -> String {
+        self.members[idx]
+            .local_address
+            .map_or_else(|| idx.to_string(), |address| address.to_string())
+    }
+
+    pub(crate) fn record(&self, idx: usize, meta: &ResponseMeta) {
+        if let Some(weight) = meta.used_weight_1m {
+            let minute = meta
+                .server_time
+                .map_or_else(current_minute, |t| t.timestamp() / 60);
+            *self.members[idx]
+                .used
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner) = UsedWeight { minute, weight };
+        }
+    }
+}
+
+fn current_minute() -> i64 {
+    Utc::now().timestamp() / 60
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Balance, ClientPool};
+    use crate::{response::ResponseMeta, transport::Priority};
+    use failure::Fallible;
+    use http::{HeaderMap, HeaderValue, StatusCode};
+    use std::{net::IpAddr, time::Duration};
+
+    fn weight(used: &'static str) -> ResponseMeta {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static(used));
+        ResponseMeta::new(StatusCode::OK, headers, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn balancing() -> Fallible<()> {
+        let addresses: Vec<IpAddr> = vec!["10.0.0.1".parse()?, "10.0.0.2".parse()?];
+        let clients = || addresses.iter().map(|&a| (reqwest::Client::new(), Some(a)));
+
+        let round_robin = ClientPool::from_clients(clients(), Balance::RoundRobin)?;
+        let picks: Vec<_> = (0..4).map(|_| round_robin.pick(Priority::Normal)).collect();
+        assert_eq!(picks, vec![0, 1, 0, 1]);
+
+        let headroom = ClientPool::from_clients(clients(), Balance::Headroom)?;
+        headroom.record(0, &weight("900"));
+        headroom.record(1, &weight("100"));
+        assert_eq!(headroom.pick(Priority::Normal), 1);
+        assert_eq!(headroom.pick(Priority::Normal), 1);
+        headroom.record(1, &weight("1000"));
+        assert_eq!(headroom.pick(Priority::Normal), 0);
+        assert_eq!(
+            headroom.used_weights(),
+            vec![(Some(addresses[0]), 900), (Some(addresses[1]), 1000)]
+        );
+
+        assert!(ClientPool::from_clients(Vec::new(), Balance::RoundRobin).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn priorities() -> Fallible<()> {
+        let addresses: Vec<IpAddr> = vec![
+            "10.0.0.1".parse()?,
+            "10.0.0.2".parse()?,
+            "10.0.0.3".parse()?,
+        ];
+        let pool = ClientPool::from_clients(
+            addresses.iter().map(|&a| (reqwest::Client::new(), Some(a))),
+            Balance::RoundRobin,
+        )?;
+        pool.record(0, &weight("500"));
+        pool.record(1, &weight("100"));
+        pool.record(2, &weight("300"));
+
+        let high: Vec<_> = (0..3).map(|_| pool.pick(Priority::High)).collect();
+        assert_eq!(high, vec![1, 1, 1]);
+        let low: Vec<_> = (0..3).map(|_| pool.pick(Priority::Low)).collect();
+        assert_eq!(low, vec![2, 2, 2]);
+        let normal: Vec<_> = (0..3).map(|_| pool.pick(Priority::Normal)).collect();
+        assert_eq!(normal, vec![0, 1, 2]);
+
+        let single = ClientPool::single(reqwest::Client::new(), None);
+        assert_eq!(single.pick(Priority::Low), 0);
+        Ok(())
+    }
+}
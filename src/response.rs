@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use http::{header::DATE, HeaderMap, StatusCode};
+use std::time::Duration;
+
+const USED_WEIGHT_1M: &str = "x-mbx-used-weight-1m";
+const ORDER_COUNT_10S: &str = "x-mbx-order-count-10s";
+const ORDER_COUNT_1D: &str = "x-mbx-order-count-1d";
+
+// HTTP details of a REST response
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub used_weight_1m: Option<u64>,
+    pub order_count_10s: Option<u64>,
+    pub order_count_1d: Option<u64>,
+    pub server_time: Option<DateTime<Utc>>, // from the Date header, second precision
+    pub latency: Duration,                  // until the headers arrived
+}
+
+impl ResponseMeta {
+    #[must_use]
+    pub fn new(status: StatusCode, headers: HeaderMap, latency: Duration) -> Self {
+        let counter = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+        };
+        let server_time = headers
+            .get(DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .map(|t| t.with_timezone(&Utc));
+
+        Self {
+            status,
+            used_weight_1m: counter(USED_WEIGHT_1M),
+            order_count_10s: counter(ORDER_COUNT_10S),
+            order_count_1d: counter(ORDER_COUNT_1D),
+            server_time,
+            latency,
+            headers,
+        }
+    }
+
+    // Used weight for any interval header, e.g. "1m" or "1h"
+    #[must_use]
+    pub fn used_weight(&self, interval: &str) -> Option<u64> {
+        self.headers
+            .get(format!("x-mbx-used-weight-{}", interval).as_str())
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    }
+}
+
+// Result of a request along with the response it was read from
+#[derive(Debug, Clone)]
+pub struct Response<T> {
+    pub meta: ResponseMeta,
+    pub data: T,
+}
+
+impl<T> Response<T> {
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ResponseMeta;
+    use chrono::{DateTime, Utc};
+    use http::{HeaderMap, HeaderValue, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("42"));
+        headers.insert("x-mbx-used-weight", HeaderValue::from_static("42"));
+        headers.insert("x-mbx-order-count-10s", HeaderValue::from_static("3"));
+        headers.insert(
+            "date",
+            HeaderValue::from_static("Tue, 02 Jun 2020 10:15:30 GMT"),
+        );
+
+        let meta = ResponseMeta::new(StatusCode::OK, headers, Duration::from_millis(15));
+        assert_eq!(meta.used_weight_1m, Some(42));
+        assert_eq!(meta.used_weight("1m"), Some(42));
+        assert_eq!(meta.order_count_10s, Some(3));
+        assert_eq!(meta.order_count_1d, None);
+        assert_eq!(
+            meta.server_time,
+            "2020-06-02T10:15:30Z".parse::<DateTime<Utc>>().ok()
+        );
+    }
+}
@@ -0,0 +1,112 @@
+// Metrics reported through the `metrics` facade. Install any recorder,
+// e.g. metrics-exporter-prometheus, to collect them.
+//
+// | name                               | kind      | labels                     |
+// |------------------------------------|-----------|----------------------------|
+// | binance_requests_total             | counter   | endpoint, method, status   |
+// | binance_request_errors_total       | counter   | endpoint, code             |
+// | binance_request_duration_seconds   | histogram | endpoint                   |
+// | binance_used_weight_1m             | gauge     | client                     |
+// | binance_ws_reconnects_total        | counter   |                            |
+// | binance_ws_messages_total          | counter   | stream                     |
+// | binance_ws_event_lag_seconds       | histogram | stream                     |
+//
+// `code` is the Binance error code, or "transport" for failures without one.
+// `client` is the local address of the pooled client, or its index in the
+// pool when it is not bound to one. Binance counts weight per IP.
+// User data streams are labelled "userData" rather than by listen key.
+
+use crate::response::ResponseMeta;
+use http::Method;
+use metrics::{counter, gauge, histogram};
+
+pub const REQUESTS: &str = "binance_requests_total";
+pub const REQUEST_ERRORS: &str = "binance_request_errors_total";
+pub const REQUEST_DURATION: &str = "binance_request_duration_seconds";
+pub const USED_WEIGHT: &str = "binance_used_weight_1m";
+pub const WS_RECONNECTS: &str = "binance_ws_reconnects_total";
+pub const WS_MESSAGES: &str = "binance_ws_messages_total";
+pub const WS_EVENT_LAG: &str = "binance_ws_event_lag_seconds";
+
+pub(crate) fn response(endpoint: &str, method: &Method, client: String, meta: &ResponseMeta) {
+    counter!(
+        REQUESTS,
+        "endpoint" => endpoint.to_string(),
+        "method" => method.to_string(),
+        "status" => meta.status.as_u16().to_string(),
+    )
+    .increment(1);
+    histogram!(REQUEST_DURATION, "endpoint" => endpoint.to_string())
+        .record(meta.latency.as_secs_f64());
+    if let Some(weight) = meta.used_weight_1m {
+        #[allow(clippy::cast_precision_loss)]
+        gauge!(USED_WEIGHT, "client" => client).set(weight as f64);
+    }
+}
+
+pub(crate) fn request_error(endpoint: &str, code: Option<i64>) {
+    let code = code.map_or_else(|| "transport".to_string(), |code| code.to_string());
+    counter!(REQUEST_ERRORS, "endpoint" => endpoint.to_string(), "code" => code).increment(1);
+}
+
+pub(crate) fn reconnect() {
+    counter!(WS_RECONNECTS).increment(1);
+}
+
+pub(crate) fn message(stream: &str, lag_ms: Option<i64>) {
+    counter!(WS_MESSAGES, "stream" => stream.to_string()).increment(1);
+    if let Some(lag_ms) = lag_ms {
+        #[allow(clippy::cast_precision_loss)]
+        histogram!(WS_EVENT_LAG, "stream" => stream.to_string()).record(lag_ms as f64 / 1000.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{REQUESTS, REQUEST_ERRORS, USED_WEIGHT, WS_MESSAGES};
+    use crate::response::ResponseMeta;
+    use http::{HeaderMap, HeaderValue, Method, StatusCode};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::time::Duration;
+
+    #[test]
+    fn recorded_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("17"));
+            let meta = ResponseMeta::new(StatusCode::OK, headers, Duration::from_millis(20));
+            super::response("/api/v3/depth", &Method::GET, "10.0.0.1".into(), &meta);
+            super::response("/api/v3/depth", &Method::GET, "10.0.0.2".into(), &meta);
+            super::request_error("/api/v3/order", Some(-2010));
+            super::message("bnbbtc@depth", Some(150));
+            super::message("bnbbtc@depth", None);
+        });
+
+        let values: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+            .collect();
+        let gauges = values
+            .iter()
+            .filter(|(name, _)| name == USED_WEIGHT)
+            .count();
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value)
+        };
+
+        assert_eq!(value(REQUESTS), Some(&DebugValue::Counter(2)));
+        assert_eq!(value(REQUEST_ERRORS), Some(&DebugValue::Counter(1)));
+        assert_eq!(value(WS_MESSAGES), Some(&DebugValue::Counter(2)));
+        assert_eq!(value(USED_WEIGHT), Some(&DebugValue::Gauge(17.0.into())));
+        // One series per pooled client
+        assert_eq!(gauges, 2);
+    }
+}
@@ -1,22 +1,34 @@
-use crate::error::{BinanceResponse, Error};
+use crate::{
+    config::ClientConfig,
+    credentials::Credentials,
+    error::{BinanceResponse, Error},
+    middleware::Middleware,
+    pool::ClientPool,
+    response::{Response, ResponseMeta},
+    signer::{HmacSigner, Signer},
+};
 use chrono::Utc;
 use failure::Fallible;
 use futures::prelude::*;
 use headers::*;
-use hex::encode as hexify;
-use hmac::{Hmac, Mac};
 use http::Method;
 use once_cell::sync::OnceCell;
 use reqwest_ext::*;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{to_string, to_value, Value};
-use sha2::Sha256;
-use std::str::FromStr;
+use std::{
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tracing::*;
 use url::Url;
 
 const BASE: &str = "https://www.binance.com";
 const RECV_WINDOW: usize = 5000;
+const MAX_RECV_WINDOW: u64 = 60000;
 
 pub struct BinanceApiKey(pub String);
 
@@ -44,11 +56,92 @@ impl headers::Header for BinanceApiKey {
     }
 }
 
+// Which pooled client a request is sent on, see `ClientPool::pick`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+// Per-request overrides of the transport defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    recv_window: Option<u64>, // ms
+    timeout: Option<Duration>,
+    retries: u32,
+    priority: Priority,
+}
+
+impl RequestOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Binance rejects windows above 60000ms
+    pub fn recv_window(mut self, recv_window: u64) -> Fallible<Self> {
+        if recv_window == 0 || recv_window > MAX_RECV_WINDOW {
+            return Err(Error::InvalidRecvWindow { recv_window }.into());
+        }
+        self.recv_window = Some(recv_window);
+        Ok(self)
+    }
+
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Times to resend a request that failed to connect. Requests that reached
+    // Binance are never resent, so this is safe for order placement.
+    #[must_use]
+    pub const fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    #[must_use]
+    pub const fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    #[must_use]
+    pub const fn get_priority(&self) -> Priority {
+        self.priority
+    }
+}
+
+// Clones share the signer, key material is never copied
 #[derive(Clone)]
 pub struct Transport {
-    credential: Option<(String, String)>,
-    client: reqwest::Client,
+    signer: Option<Arc<dyn Signer>>,
+    pool: Arc<ClientPool>,
+    // Pool set with `with_pool`, which `with_config` must not replace
+    pooled: bool,
     pub recv_window: usize,
+    options: RequestOptions,
+    last_meta: Arc<Mutex<Option<ResponseMeta>>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    config: Arc<ClientConfig>,
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("signer", &self.signer.as_ref().map(|_| "[REDACTED]"))
+            .field("recv_window", &self.recv_window)
+            .field("options", &self.options)
+            .field("pool", &self.pool)
+            .field("pooled", &self.pooled)
+            .field("last_meta", &self.last_meta)
+            .field("middleware", &self.middleware.len())
+            .field("config", &self.config)
+            .finish()
+    }
 }
 
 impl Default for Transport {
@@ -60,17 +153,153 @@ impl Default for Transport {
 impl Transport {
     pub fn new() -> Self {
         Self {
-            credential: None,
-            client: reqwest::Client::builder().build().unwrap(),
+            signer: None,
+            pool: Arc::new(ClientPool::single(reqwest::Client::new(), None)),
+            pooled: false,
             recv_window: RECV_WINDOW,
+            options: RequestOptions::default(),
+            last_meta: Arc::default(),
+            middleware: Vec::new(),
+            config: Arc::default(),
         }
     }
 
     pub fn with_credential(api_key: &str, api_secret: &str) -> Self {
+        Self::with_signer(HmacSigner::new(api_key, api_secret))
+    }
+
+    pub fn with_credentials(credentials: Credentials) -> Self {
+        Self::with_signer(HmacSigner::from(credentials))
+    }
+
+    pub fn with_signer<S: Signer + 'static>(signer: S) -> Self {
         Self {
-            client: reqwest::Client::builder().build().unwrap(),
-            credential: Some((api_key.into(), api_secret.into())),
+            pool: Arc::new(ClientPool::single(reqwest::Client::new(), None)),
+            pooled: false,
+            signer: Some(Arc::new(signer)),
             recv_window: RECV_WINDOW,
+            options: RequestOptions::default(),
+            last_meta: Arc::default(),
+            middleware: Vec::new(),
+            config: Arc::default(),
+        }
+    }
+
+    // Copy of the transport that applies the options to every request
+    #[must_use]
+    pub fn with_options(&self, options: RequestOptions) -> Self {
+        Self {
+            options,
+            ..self.clone()
+        }
+    }
+
+    // Copy of the transport sending requests with the given client.
+    // Websockets keep using the settings of the current config.
+    #[must_use]
+    pub fn with_client(&self, client: reqwest::Client) -> Self {
+        Self {
+            pool: Arc::new(ClientPool::single(client, None)),
+            pooled: false,
+            ..self.clone()
+        }
+    }
+
+    // Copy of the transport spreading requests over the clients of the pool
+    #[must_use]
+    pub fn with_pool(&self, pool: ClientPool) -> Self {
+        Self {
+            pool: Arc::new(pool),
+            pooled: true,
+            ..self.clone()
+        }
+    }
+
+    #[must_use]
+    pub fn pool(&self) -> &ClientPool {
+        &self.pool
+    }
+
+    // Copy of the transport with timeouts, proxy and pooling from the config,
+    // websockets opened through it use the same proxy and connect timeout.
+    // Fails after `with_pool`, build the pool with
+    // `ClientPool::from_local_addresses` and apply it after the config instead.
+    pub fn with_config(&self, config: ClientConfig) -> Fallible<Self> {
+        if self.pooled {
+            return Err(failure::format_err!(
+                "Config would replace the client pool, apply it before the pool"
+            ));
+        }
+        Ok(Self {
+            pool: Arc::new(ClientPool::single(
+                config.http_client()?,
+                config.local_address,
+            )),
+            config: Arc::new(config),
+            ..self.clone()
+        })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    // Copy of the transport that runs every request through the middleware,
+    // after any middleware added before
+    #[must_use]
+    pub fn with_middleware<M: Middleware + 'static>(&self, middleware: M) -> Self {
+        let mut transport = self.clone();
+        transport.middleware.push(Arc::new(middleware));
+        transport
+    }
+
+    // Copy of the transport recording response metadata apart from its clones
+    pub(crate) fn with_own_meta(&self) -> Self {
+        Self {
+            last_meta: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    // Metadata of the latest response received by this transport or its clones.
+    // Best effort only, concurrent requests overwrite it.
+    #[must_use]
+    pub fn last_response_meta(&self) -> Option<ResponseMeta> {
+        self.last_meta
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn recv_window(&self) -> Fallible<u64> {
+        let recv_window = self.options.recv_window.unwrap_or(self.recv_window as u64);
+        if recv_window > MAX_RECV_WINDOW {
+            return Err(Error::InvalidRecvWindow { recv_window }.into());
+        }
+        Ok(recv_window)
+    }
+
+    fn base_url(&self) -> &str {
+        self.config
+            .rest_url
+            .as_ref()
+            .map_or(BASE, |url| url.as_str().trim_end_matches('/'))
+    }
+
+    // Picks the pooled client the request is built and executed on
+    fn build(&self, method: Method, url: &Url) -> (usize, reqwest::RequestBuilder) {
+        let idx = self.pool.pick(self.options.priority);
+        let req = self
+            .pool
+            .client(idx)
+            .request(method, url.as_str())
+            .typed_header(headers::UserAgent::from_static("binance-rs"))
+            .typed_header(headers::ContentType::form_url_encoded());
+
+        match self.options.timeout {
+            Some(timeout) => (idx, req.timeout(timeout)),
+            None => (idx, req),
         }
     }
 
@@ -182,7 +411,24 @@ impl Transport {
         Q: Serialize,
         D: Serialize,
     {
-        let url = format!("{}{}", BASE, endpoint);
+        Ok(self
+            .request_with_meta(method, endpoint, params, data)?
+            .map_ok(Response::into_inner))
+    }
+
+    pub fn request_with_meta<O, Q, D>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        params: Option<Q>,
+        data: Option<D>,
+    ) -> Fallible<impl Future<Output = Fallible<Response<O>>>>
+    where
+        O: DeserializeOwned,
+        Q: Serialize,
+        D: Serialize,
+    {
+        let url = format!("{}{}", self.base_url(), endpoint);
         let url = match params {
             Some(p) => Url::parse_with_params(&url, p.to_url_query())?,
             None => Url::parse(&url)?,
@@ -193,27 +439,16 @@ impl Transport {
             None => "".to_string(),
         };
 
-        let mut req = self
-            .client
-            .request(method, url.as_str())
-            .typed_header(headers::UserAgent::from_static("binance-rs"))
-            .typed_header(headers::ContentType::form_url_encoded());
+        let (idx, mut req) = self.build(method, &url);
 
-        if let Ok((key, _)) = self.check_key() {
+        if let Ok(key) = self.api_key() {
             // This is for user stream: user stream requests need api key in the header but no signature. WEIRD
             req = req.typed_header(BinanceApiKey(key.to_string()));
         }
 
         let req = req.body(body);
 
-        Ok(async move {
-            Ok(req
-                .send()
-                .await?
-                .json::<BinanceResponse<_>>()
-                .await?
-                .into_result()?)
-        })
+        Ok(self.send(idx, req))
     }
 
     pub fn signed_request<O, Q, D>(
@@ -223,61 +458,218 @@ impl Transport {
         params: Option<Q>,
         data: Option<D>,
     ) -> Fallible<impl Future<Output = Fallible<O>>>
+    where
+        O: DeserializeOwned,
+        Q: Serialize,
+        D: Serialize,
+    {
+        Ok(self
+            .signed_request_with_meta(method, endpoint, params, data)?
+            .map_ok(Response::into_inner))
+    }
+
+    pub fn signed_request_with_meta<O, Q, D>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        params: Option<Q>,
+        data: Option<D>,
+    ) -> Fallible<impl Future<Output = Fallible<Response<O>>>>
     where
         O: DeserializeOwned,
         Q: Serialize,
         D: Serialize,
     {
         let query = params.map_or_else(Vec::new, |q| q.to_url_query());
-        let url = format!("{}{}", BASE, endpoint);
+        let url = format!("{}{}", self.base_url(), endpoint);
         let mut url = Url::parse_with_params(&url, &query)?;
         url.query_pairs_mut()
             .append_pair("timestamp", &Utc::now().timestamp_millis().to_string());
         url.query_pairs_mut()
-            .append_pair("recvWindow", &self.recv_window.to_string());
+            .append_pair("recvWindow", &self.recv_window()?.to_string());
 
         let body = data.map_or_else(String::new, |data| data.to_url_query_string());
 
         let (key, signature) = self.signature(&url, &body)?;
         url.query_pairs_mut().append_pair("signature", &signature);
 
-        let req = self
-            .client
-            .request(method, url.as_str())
-            .typed_header(headers::UserAgent::from_static("binance-rs"))
-            .typed_header(headers::ContentType::form_url_encoded())
-            .typed_header(BinanceApiKey(key.to_string()))
-            .body(body);
-
-        Ok(async move {
-            Ok(req
-                .send()
-                .await?
-                .json::<BinanceResponse<_>>()
-                .await?
-                .into_result()?)
-        })
+        let (idx, req) = self.build(method, &url);
+        let req = req.typed_header(BinanceApiKey(key.to_string())).body(body);
+
+        Ok(self.send(idx, req))
     }
 
-    fn check_key(&self) -> Fallible<(&str, &str)> {
-        match self.credential.as_ref() {
+    fn check_key(&self) -> Fallible<&dyn Signer> {
+        match self.signer.as_ref() {
             None => Err(Error::NoApiKeySet.into()),
-            Some((k, s)) => Ok((k, s)),
+            Some(signer) => Ok(signer.as_ref()),
         }
     }
 
     pub(self) fn signature(&self, url: &Url, body: &str) -> Fallible<(&str, String)> {
-        let (key, secret) = self.check_key()?;
-        // Signature: hex(HMAC_SHA256(queries + data))
-        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        // Signature: sign(queries + data)
         let sign_message = format!("{}{}", url.query().unwrap_or(""), body);
-        trace!("Sign message: {}", sign_message);
-        mac.input(sign_message.as_bytes());
-        let signature = hexify(mac.result().code());
-        Ok((key, signature))
+        self.sign(&sign_message)
+    }
+
+    pub(crate) fn api_key(&self) -> Fallible<&str> {
+        Ok(self.check_key()?.api_key())
+    }
+
+    // Signs an arbitrary payload, returns the api key along with the signature.
+    // Neither the payload nor the signature may be logged.
+    pub(crate) fn sign(&self, sign_message: &str) -> Fallible<(&str, String)> {
+        let signer = self.check_key()?;
+        let signature = signer.sign(sign_message.as_bytes())?;
+        Ok((signer.api_key(), signature))
+    }
+}
+
+impl Transport {
+    fn send<O: DeserializeOwned>(
+        &self,
+        idx: usize,
+        req: reqwest::RequestBuilder,
+    ) -> impl Future<Output = Fallible<Response<O>>> {
+        let req = req.build();
+        let span = req.as_ref().map_or_else(|_| Span::none(), request_span);
+        let pool = self.pool.clone();
+        let retries = self.options.retries;
+        let last_meta = self.last_meta.clone();
+        let middleware = self.middleware.clone();
+
+        async move {
+            let mut req = req?;
+            for m in &middleware {
+                m.before_request(&mut req)?;
+            }
+            let (method, url) = (req.method().clone(), req.url().clone());
+
+            let response = execute(pool.client(idx), req, retries, &last_meta).await;
+            if let Ok((meta, _)) = &response {
+                let span = Span::current();
+                span.record("status", meta.status.as_u16());
+                span.record("weight", meta.used_weight_1m);
+                span.record(
+                    "latency_ms",
+                    u64::try_from(meta.latency.as_millis()).unwrap_or(u64::MAX),
+                );
+                pool.record(idx, meta);
+                #[cfg(feature = "metrics")]
+                crate::telemetry::response(url.path(), &method, pool.label(idx), meta);
+                for m in &middleware {
+                    m.after_response(&method, &url, meta);
+                }
+            }
+            #[cfg(feature = "metrics")]
+            {
+                if response.is_err() {
+                    crate::telemetry::request_error(url.path(), None);
+                }
+            }
+
+            let (meta, resp) = response?;
+            let data = match resp.json::<BinanceResponse<_>>().await?.into_result() {
+                Ok(data) => data,
+                Err(e) => {
+                    if let Error::BinanceError { code, .. } = &e {
+                        Span::current().record("error_code", code);
+                        #[cfg(feature = "metrics")]
+                        crate::telemetry::request_error(url.path(), Some(*code));
+                    }
+                    debug!("[Transport] Request failed: {}", e);
+                    return Err(e.into());
+                }
+            };
+            Ok(Response { meta, data })
+        }
+        .instrument(span)
     }
 }
 
+// Span per request, fields past the symbol are filled in once the response arrives.
+// Only the path and symbol are taken from the request, the query carries the signature.
+fn request_span(req: &reqwest::Request) -> Span {
+    let body = req.body().and_then(reqwest::Body::as_bytes).unwrap_or(&[]);
+    let symbol = req
+        .url()
+        .query_pairs()
+        .chain(url::form_urlencoded::parse(body))
+        .find(|(key, _)| key == "symbol")
+        .map(|(_, symbol)| symbol.into_owned());
+
+    info_span!(
+        "binance_request",
+        endpoint = req.url().path(),
+        method = %req.method(),
+        symbol = symbol.as_deref(),
+        weight = field::Empty,
+        status = field::Empty,
+        error_code = field::Empty,
+        latency_ms = field::Empty,
+    )
+}
+
+// Resends requests that failed to connect, those never reached Binance
+async fn execute(
+    client: &reqwest::Client,
+    mut req: reqwest::Request,
+    retries: u32,
+    last_meta: &Mutex<Option<ResponseMeta>>,
+) -> Fallible<(ResponseMeta, reqwest::Response)> {
+    let endpoint = req.url().path().to_string();
+    let mut attempt = 0;
+    loop {
+        let sent = Instant::now();
+        let retry = if attempt < retries {
+            req.try_clone()
+        } else {
+            None
+        };
+
+        match (client.execute(req).await, retry) {
+            (Err(e), Some(retry)) if e.is_connect() => {
+                attempt += 1;
+                warn!(
+                    "Connection to {} failed, retrying ({}/{}): {}",
+                    endpoint,
+                    attempt,
+                    retries,
+                    scrub(&e)
+                );
+                req = retry;
+            }
+            (resp, _) => {
+                let resp = resp.map_err(|e| Error::RequestFailed {
+                    endpoint: endpoint.clone(),
+                    reason: scrub(&e),
+                })?;
+                let meta = ResponseMeta::new(resp.status(), resp.headers().clone(), sent.elapsed());
+                // Recorded before the body is checked, error responses count towards limits too
+                *last_meta
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(meta.clone());
+                return Ok((meta, resp));
+            }
+        }
+    }
+}
+
+// reqwest errors name the full URL, whose query carries the signature
+fn scrub(e: &reqwest::Error) -> String {
+    let kind = if e.is_timeout() {
+        "timed out"
+    } else if e.is_connect() {
+        "connection failed"
+    } else {
+        "error sending request"
+    };
+    std::error::Error::source(e).map_or_else(
+        || kind.to_string(),
+        |source| format!("{}: {}", kind, source),
+    )
+}
+
 trait ToUrlQuery: Serialize {
     fn to_url_query_string(&self) -> String {
         let vec = self.to_url_query();
@@ -309,10 +701,35 @@ impl<S: Serialize> ToUrlQuery for S {}
 
 #[cfg(test)]
 mod test {
-    use super::Transport;
+    use super::{RequestOptions, Transport};
+    use crate::{
+        config::ClientConfig,
+        pool::{Balance, ClientPool},
+    };
     use failure::Fallible;
+    use http::Method;
+    use serde_json::Value;
+    use std::{
+        io,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
     use url::{form_urlencoded::Serializer, Url};
 
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn signature_query() -> Fallible<()> {
         let tr = Transport::with_credential(
@@ -429,4 +846,73 @@ mod test {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn retry_redaction() -> Fallible<()> {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // Nothing listens on the port once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let config = ClientConfig {
+            rest_url: Some(Url::parse(&format!("http://127.0.0.1:{}", port))?),
+            ..ClientConfig::default()
+        };
+        let tr = Transport::with_credential("key", "secret")
+            .with_config(config)?
+            .with_options(RequestOptions::new().retries(1));
+        let e = tr
+            .signed_get::<Value, ()>("/api/v3/account", None)?
+            .await
+            .unwrap_err();
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+        assert!(logs.contains("Connection to /api/v3/account failed, retrying (1/1)"));
+        assert!(!logs.contains("signature"));
+        assert!(!e.to_string().contains("signature"));
+        assert!(!format!("{:?}", e).contains("signature"));
+        Ok(())
+    }
+
+    #[test]
+    fn request_options() -> Fallible<()> {
+        assert!(RequestOptions::new().recv_window(60001).is_err());
+        assert!(RequestOptions::new().recv_window(0).is_err());
+
+        let tr = Transport::new();
+        assert_eq!(tr.recv_window()?, 5000);
+        let tight = tr.with_options(RequestOptions::new().recv_window(1000)?);
+        assert_eq!(tight.recv_window()?, 1000);
+        assert_eq!(tr.recv_window()?, 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn config_and_pool() -> Fallible<()> {
+        let pool = || {
+            ClientPool::from_clients(
+                vec![
+                    (reqwest::Client::new(), None),
+                    (reqwest::Client::new(), None),
+                ],
+                Balance::RoundRobin,
+            )
+        };
+
+        let tr = Transport::new().with_pool(pool()?);
+        assert!(tr.with_config(ClientConfig::default()).is_err());
+
+        let tr = Transport::new()
+            .with_config(ClientConfig::default())?
+            .with_pool(pool()?);
+        assert_eq!(tr.pool().used_weights().len(), 2);
+        let url = Url::parse("http://a.com/api/v3/ping")?;
+        let picks: Vec<_> = (0..3).map(|_| tr.build(Method::GET, &url).0).collect();
+        assert_eq!(picks, vec![0, 1, 0]);
+        Ok(())
+    }
 }
@@ -0,0 +1,332 @@
+#![cfg(feature = "mock")]
+
+use binance_async as binance;
+
+use failure::Fallible;
+use futures::prelude::*;
+use serde_json::json;
+use std::time::Duration;
+
+use crate::binance::{
+    error::Error,
+    mock::MockServer,
+    model::{
+        websocket::{
+            BinanceWebsocketMessage, Subscription, TradeMessage, UserDataEvent, UserOrderUpdate,
+        },
+        OrderExecType,
+    },
+    BinanceWebsocket, BinanceWsApi, LocalOrderBook, PaperBinance, PaperUserStream, UserStreamKind,
+};
+use tokio::net::TcpStream;
+use tungstenite::Message;
+use url::Url;
+
+fn binance_code(e: &failure::Error) -> Option<i64> {
+    match e.downcast_ref::<Error>() {
+        Some(Error::BinanceError { code, .. }) => Some(*code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn order_flow() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    server.set_balance("BTC", 1.0);
+    let binance = server.binance()?;
+
+    binance.ping()?.await?;
+    assert!((binance.get_price("BNBBTC")?.await? - 0.002).abs() < f64::EPSILON);
+
+    let order = binance.limit_buy("BNBBTC", 100.0, 0.001)?.await?;
+    assert_eq!(binance.get_open_orders("BNBBTC")?.await?.len(), 1);
+    assert_eq!(binance.get_balance("BTC")?.await?.locked, "0.10000000");
+
+    server.set_price("BNBBTC", 0.001);
+    let status = binance.order_status("BNBBTC", order.order_id)?.await?;
+    assert_eq!(status.status, "FILLED");
+    assert_eq!(binance.get_balance("BNB")?.await?.free, "100.00000000");
+    assert_eq!(binance.trade_history("BNBBTC")?.await?.len(), 1);
+
+    let e = binance
+        .cancel_order("BNBBTC", order.order_id)?
+        .await
+        .unwrap_err();
+    assert_eq!(binance_code(&e), Some(-2011));
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejections() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    let binance = server.binance()?;
+
+    server.fail_next("/api/v3/account", 400, -1013, "Filter failure: LOT_SIZE");
+    let e = binance.get_account()?.await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1013));
+
+    let e = binance.market_buy("BNBBTC", 1.0)?.await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-2010));
+
+    let wrong_secret = binance::Binance::with_credential(binance::mock::API_KEY, "wrong")
+        .with_config(server.config())?;
+    let e = wrong_secret.get_account()?.await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1022));
+
+    server.set_weight_limit(Some(0));
+    let e = binance.get_account()?.await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1003));
+    assert_eq!(
+        binance
+            .last_response_meta()
+            .map(|meta| meta.status.as_u16()),
+        Some(429)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn response_meta() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    let binance = server.binance()?;
+
+    let (ping, account) = future::try_join(
+        binance.with_meta(binance::Binance::ping),
+        binance.with_meta(binance::Binance::get_account),
+    )
+    .await?;
+    assert_eq!(ping.meta.status.as_u16(), 200);
+    assert_eq!(account.meta.status.as_u16(), 200);
+    // Each call sees the weight used up to its own request
+    assert!(matches!(ping.meta.used_weight_1m, Some(1) | Some(11)));
+    assert!(matches!(account.meta.used_weight_1m, Some(10) | Some(11)));
+
+    server.fail_next("/api/v3/account", 400, -1013, "Filter failure: LOT_SIZE");
+    let e = binance
+        .with_meta(binance::Binance::get_account)
+        .await
+        .unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1013));
+    Ok(())
+}
+
+#[tokio::test]
+async fn streams() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    server.set_balance("BTC", 1.0);
+    let binance = server.binance()?;
+
+    let listen_key = binance
+        .user_stream_start(&UserStreamKind::Spot)?
+        .await?
+        .listen_key;
+    let mut websocket = BinanceWebsocket::with_config(server.config());
+    websocket
+        .subscribe(Subscription::UserData(listen_key))
+        .await?;
+
+    binance.market_buy("BNBBTC", 10.0)?.await?;
+    let executions: Vec<_> = websocket
+        .by_ref()
+        .take(3)
+        .map_ok(|msg| match msg {
+            BinanceWebsocketMessage::UserData(UserDataEvent::ExecutionReport(update)) => {
+                format!("{:?}", update.execution_type)
+            }
+            BinanceWebsocketMessage::UserData(UserDataEvent::AccountPosition(_)) => {
+                "Position".to_string()
+            }
+            other => format!("{:?}", other),
+        })
+        .try_collect()
+        .await?;
+    assert_eq!(executions, vec!["New", "Trade", "Position"]);
+
+    websocket
+        .subscribe(Subscription::Trade("bnbbtc".to_string()))
+        .await?;
+    server.publish(
+        "bnbbtc@trade",
+        json!({
+            "e": "trade", "E": 1, "s": "BNBBTC", "t": 1, "p": "0.002", "q": "1",
+            "b": 1, "a": 2, "T": 1, "m": true, "M": true
+        }),
+    );
+    match websocket.try_next().await? {
+        Some(BinanceWebsocketMessage::Trade(trade)) => assert_eq!(trade.symbol, "BNBBTC"),
+        other => panic!("unexpected message {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn typed_subscriptions() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    let trades = Subscription::Trade("BNBBTC".to_string());
+    let mut websocket = BinanceWebsocket::with_config(server.config());
+    websocket.set_idle_timeout(&trades, Some(Duration::from_millis(200)));
+    let handle = websocket.spawn()?;
+
+    let mut stream = handle.subscribe_typed::<TradeMessage>(trades).await?;
+    server.publish(
+        "bnbbtc@trade",
+        json!({
+            "e": "trade", "E": 1, "s": "BNBBTC", "t": 7, "p": "0.002", "q": "1",
+            "b": 1, "a": 2, "T": 1, "m": true, "M": true
+        }),
+    );
+    let trade = stream.try_next().await?.expect("a trade");
+    assert_eq!(trade.trade_id, 7);
+
+    // Silence past the idle timeout surfaces as an error on the typed stream
+    let e = stream.next().await.expect("an event").unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<Error>(),
+        Some(Error::StreamStale)
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn ws_api() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    server.set_balance("BTC", 1.0);
+    let api = BinanceWsApi::connect(&server.binance()?).await?;
+
+    // Pipelined over the one connection
+    let (price, _, account) =
+        future::try_join3(api.get_price("BNBBTC"), api.ping(), api.get_account()).await?;
+    assert!((price - 0.002).abs() < f64::EPSILON);
+    assert!(account.balances.iter().any(|b| b.asset == "BTC"));
+
+    let order = api.limit_buy("BNBBTC", 100.0, 0.001).await?;
+    assert_eq!(api.get_open_orders("BNBBTC").await?.len(), 1);
+    let status = api.order_status("BNBBTC", order.order_id).await?;
+    assert_eq!(status.status, "NEW");
+    let canceled = api.cancel_order("BNBBTC", order.order_id).await?;
+    assert_eq!(canceled.order_id, order.order_id);
+
+    let e = api.market_buy("BNBBTC", 1000.0).await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-2010));
+    server.fail_next("order.place", 400, -1013, "Filter failure: LOT_SIZE");
+    let e = api.market_buy("BNBBTC", 1.0).await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1013));
+
+    let wrong_secret = binance::Binance::with_credential(binance::mock::API_KEY, "wrong")
+        .with_config(server.config())?;
+    let api = BinanceWsApi::connect(&wrong_secret).await?;
+    let e = api.get_account().await.unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1022));
+    Ok(())
+}
+
+#[tokio::test]
+async fn malformed_frames() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    let config = server.config();
+
+    for url in [config.stream_url, config.ws_api_url] {
+        let url: Url = url.expect("a mock endpoint");
+        let stream =
+            TcpStream::connect((url.host_str().unwrap_or_default(), url.port().unwrap_or(80)))
+                .await?;
+        let (mut socket, _) = tokio_tungstenite::client_async(url.as_str(), stream).await?;
+
+        socket
+            .send(Message::Text(json!({ "id": 7, "method": 1 }).to_string()))
+            .await?;
+        let reply = match socket.next().await {
+            Some(Ok(Message::Text(reply))) => serde_json::from_str::<serde_json::Value>(&reply)?,
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert_eq!(reply["id"], 7);
+        assert!(reply.get("code").is_some() || reply["status"] == 400);
+    }
+    Ok(())
+}
+
+// Waits for the fill of `order_id`, skipping other events
+async fn filled(events: &mut PaperUserStream, order_id: u64) -> Fallible<UserOrderUpdate> {
+    let fill = async {
+        while let Some(event) = events.try_next().await? {
+            if let UserDataEvent::ExecutionReport(report) = event {
+                if report.order_id == order_id
+                    && matches!(report.execution_type, OrderExecType::Trade)
+                {
+                    return Ok(report);
+                }
+            }
+        }
+        Err(failure::format_err!("User stream ended"))
+    };
+    tokio::time::timeout(Duration::from_secs(10), fill).await?
+}
+
+#[tokio::test]
+async fn paper_on_streams() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    server.set_balance("BTC", 1.0);
+    let binance = server.binance()?;
+    let account = binance.get_account()?.await?;
+    let paper = PaperBinance::new(binance, &account)?;
+    let mut events = paper.user_data_stream(UserStreamKind::Spot).await?;
+
+    // The first order looks the symbol up and subscribes to its streams
+    let buy = paper.limit_buy("BNBBTC", 10.0, 0.0015)?.await?;
+
+    server.publish(
+        "bnbbtc@bookTicker",
+        json!({ "u": 1, "s": "BNBBTC", "b": "0.0013", "B": "1", "a": "0.0014", "A": "1" }),
+    );
+    let fill = filled(&mut events, buy.order_id).await?;
+    assert!((fill.price_last_filled_trade - 0.0015).abs() < 1e-9);
+    let bnb: f64 = paper.get_balance("BNB")?.await?.free.parse()?;
+    assert!((bnb - (10.0 - fill.commission)).abs() < 1e-9);
+
+    let sell = paper.limit_sell("BNBBTC", 5.0, 0.003)?.await?;
+    server.publish(
+        "bnbbtc@trade",
+        json!({
+            "e": "trade", "E": 1, "s": "BNBBTC", "t": 1, "p": "0.0031", "q": "1",
+            "b": 1, "a": 2, "T": 1, "m": true, "M": true
+        }),
+    );
+    let fill = filled(&mut events, sell.order_id).await?;
+    assert!((fill.price_last_filled_trade - 0.003).abs() < 1e-9);
+    Ok(())
+}
+
+#[tokio::test]
+async fn order_book_snapshot_failures() -> Fallible<()> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    let binance = server.binance()?;
+
+    // Rate limited snapshots are refetched after a delay
+    server.fail_next("/api/v3/depth", 429, -1003, "Too many requests.");
+    let mut book = LocalOrderBook::new(binance.clone(), "BNBBTC").await?;
+    let e = book.next().await.unwrap().unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1003));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), book.next())
+            .await
+            .is_err()
+    );
+    tokio::time::timeout(Duration::from_secs(5), book.next())
+        .await?
+        .unwrap()?;
+    assert!(book.is_synced());
+
+    // Unknown symbols end the stream
+    let mut book = LocalOrderBook::new(binance, "XYZBTC").await?;
+    let e = book.next().await.unwrap().unwrap_err();
+    assert_eq!(binance_code(&e), Some(-1121));
+    assert!(book.next().await.is_none());
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// Recorded, anonymised payloads under tests/fixtures, refreshed with
+// `cargo run --example refresh_fixtures`.
+
+use binance_async as binance;
+
+use failure::{format_err, Fallible};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{fs, path::Path};
+
+use crate::binance::model::{
+    websocket::{
+        AccountPositionUpdate, AccountUpdate, AggregateTrade, BalanceUpdate, BookTickerUpdate,
+        CandelStickMessage, Depth, ListStatus, ListenKeyExpired, MiniTicker, Ticker as TickerEvent,
+        TradeMessage, UserOrderUpdate,
+    },
+    AccountInformation, AssetDetail, BookTickers, DepositAddressData, DepositHistory, ExchangeInfo,
+    ExchangeInformation, Order, OrderBook, OrderCanceled, PriceStats, Prices, ServerTime, Success,
+    TradeHistory, Transaction, UserDataStream,
+};
+
+fn load(name: &str) -> Fallible<Value> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+// Every key the model writes out must exist in the payload, catching misspelt renames
+fn assert_known_keys(name: &str, at: &str, payload: &Value, written: &Value) {
+    match (payload, written) {
+        (Value::Object(payload), Value::Object(written)) => {
+            for (key, value) in written {
+                let field = format!("{}.{}", at, key);
+                match payload.get(key) {
+                    Some(recorded) => assert_known_keys(name, &field, recorded, value),
+                    // Optional fields missing from the payload are written as null
+                    None if value.is_null() => {}
+                    None => panic!("{}: {} is not in the payload", name, field),
+                }
+            }
+        }
+        (Value::Array(payload), Value::Array(written)) => {
+            for (idx, (recorded, value)) in payload.iter().zip(written).enumerate() {
+                assert_known_keys(name, &format!("{}[{}]", at, idx), recorded, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Parses a fixture, then checks that writing and parsing it again is lossless
+fn round_trip<T: DeserializeOwned + Serialize>(name: &str) -> Fallible<()> {
+    let payload = load(name)?;
+    let parsed: T = serde_json::from_value(payload.clone())
+        .map_err(|e| format_err!("{} does not parse: {}", name, e))?;
+
+    let written = serde_json::to_value(&parsed)?;
+    assert_known_keys(name, "", &payload, &written);
+    let reparsed: T = serde_json::from_value(written.clone())
+        .map_err(|e| format_err!("{} does not parse once written: {}", name, e))?;
+    assert_eq!(serde_json::to_value(&reparsed)?, written, "{}", name);
+    Ok(())
+}
+
+#[test]
+fn rest_fixtures() -> Fallible<()> {
+    round_trip::<Success>("rest/ping.json")?;
+    round_trip::<ServerTime>("rest/time.json")?;
+    round_trip::<ExchangeInfo>("rest/exchange_info.json")?;
+    round_trip::<ExchangeInformation>("rest/exchange_info.json")?;
+    round_trip::<OrderBook>("rest/depth.json")?;
+    round_trip::<Prices>("rest/all_prices.json")?;
+    round_trip::<BookTickers>("rest/all_book_tickers.json")?;
+    round_trip::<PriceStats>("rest/ticker_24hr.json")?;
+    round_trip::<AccountInformation>("rest/account.json")?;
+    round_trip::<Order>("rest/order.json")?;
+    round_trip::<Vec<Order>>("rest/open_orders.json")?;
+    round_trip::<Transaction>("rest/order_new.json")?;
+    round_trip::<OrderCanceled>("rest/order_cancel.json")?;
+    round_trip::<Vec<TradeHistory>>("rest/my_trades.json")?;
+    round_trip::<UserDataStream>("rest/user_data_stream.json")?;
+    round_trip::<DepositAddressData>("rest/deposit_address.json")?;
+    round_trip::<DepositHistory>("rest/deposit_history.json")?;
+    round_trip::<AssetDetail>("rest/asset_detail.json")?;
+    Ok(())
+}
+
+#[test]
+fn stream_fixtures() -> Fallible<()> {
+    round_trip::<AggregateTrade>("stream/agg_trade.json")?;
+    round_trip::<TradeMessage>("stream/trade.json")?;
+    round_trip::<CandelStickMessage>("stream/kline.json")?;
+    round_trip::<CandelStickMessage>("stream/kline_empty.json")?;
+    round_trip::<MiniTicker>("stream/mini_ticker.json")?;
+    round_trip::<Vec<MiniTicker>>("stream/mini_ticker_all.json")?;
+    round_trip::<TickerEvent>("stream/ticker.json")?;
+    round_trip::<Depth>("stream/depth_update.json")?;
+    round_trip::<OrderBook>("stream/partial_depth.json")?;
+    round_trip::<BookTickerUpdate>("stream/book_ticker.json")?;
+    round_trip::<UserOrderUpdate>("stream/execution_report.json")?;
+    round_trip::<AccountUpdate>("stream/outbound_account_info.json")?;
+    round_trip::<AccountPositionUpdate>("stream/outbound_account_position.json")?;
+    round_trip::<BalanceUpdate>("stream/balance_update.json")?;
+    round_trip::<ListStatus>("stream/list_status.json")?;
+    round_trip::<ListenKeyExpired>("stream/listen_key_expired.json")?;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "mock")]
+
+// Listen key lifecycle of `UserDataStream` against the mock server
+
+use binance_async as binance;
+
+use failure::Fallible;
+use futures::prelude::*;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+use crate::binance::{
+    error::Error, mock::MockServer, model::websocket::UserDataEvent, Binance, UserDataStream,
+    UserStreamKind,
+};
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+fn listen_key(stream: &UserDataStream) -> String {
+    stream.listen_key().expect("an open listen key").to_string()
+}
+
+// Polls the stream without waiting on it until `done` holds, collecting what it
+// yields. Never idling keeps paused time from jumping to the next timer.
+async fn poll_until(
+    stream: &mut UserDataStream,
+    done: impl Fn(&UserDataStream, &[Fallible<UserDataEvent>]) -> bool,
+) -> Vec<Fallible<UserDataEvent>> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut items = Vec::new();
+    while !done(stream, &items) {
+        assert!(
+            Instant::now() < deadline,
+            "timed out polling the user stream"
+        );
+        if let Some(Some(item)) = stream.next().now_or_never() {
+            items.push(item);
+        }
+        let () = tokio::task::yield_now().await;
+    }
+    items
+}
+
+fn restarted(old: String) -> impl Fn(&UserDataStream, &[Fallible<UserDataEvent>]) -> bool {
+    move |stream, _| stream.listen_key().is_some_and(|key| key != old)
+}
+
+// Waits until the mock server no longer knows the listen key
+async fn closed(binance: &Binance, listen_key: &str) -> Fallible<()> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match binance
+            .user_stream_keep_alive(&UserStreamKind::Spot, listen_key)?
+            .await
+        {
+            Err(e) => match e.downcast_ref::<Error>() {
+                Some(Error::BinanceError { code: -1125, .. }) => return Ok(()),
+                _ => return Err(e),
+            },
+            Ok(_) => {
+                assert!(Instant::now() < deadline, "listen key was not closed");
+                let () = tokio::task::yield_now().await;
+            }
+        }
+    }
+}
+
+fn expire(server: &MockServer, listen_key: &str) {
+    server.publish(
+        listen_key,
+        json!({ "e": "listenKeyExpired", "E": 1, "listenKey": listen_key }),
+    );
+}
+
+async fn setup() -> Fallible<(MockServer, Binance)> {
+    let server = MockServer::start().await?;
+    server.add_symbol("BNBBTC", "BNB", "BTC", 0.002);
+    server.set_balance("BTC", 1.0);
+    let binance = server.binance()?;
+    Ok((server, binance))
+}
+
+#[tokio::test]
+async fn restarts_on_expiry() -> Fallible<()> {
+    let (server, binance) = setup().await?;
+    let mut stream = binance.user_data_stream(UserStreamKind::Spot).await?;
+    let old = listen_key(&stream);
+
+    expire(&server, &old);
+    let items = poll_until(&mut stream, restarted(old.clone())).await;
+    assert!(matches!(
+        items.as_slice(),
+        [Ok(UserDataEvent::ListenKeyExpired(_))]
+    ));
+    closed(&binance, &old).await?;
+
+    // Events now arrive over the new listen key
+    binance.market_buy("BNBBTC", 1.0)?.await?;
+    match stream.try_next().await? {
+        Some(UserDataEvent::ExecutionReport(report)) => assert_eq!(report.symbol, "BNBBTC"),
+        other => panic!("unexpected event {:?}", other),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn reports_reconnects() -> Fallible<()> {
+    let (server, binance) = setup().await?;
+    let mut stream = binance.user_data_stream(UserStreamKind::Spot).await?;
+    let key = listen_key(&stream);
+
+    server.drop_connections();
+    for expected in &[Error::StreamDisconnected, Error::StreamReconnected] {
+        let e = time::timeout(Duration::from_secs(10), stream.next())
+            .await?
+            .expect("an interruption")
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<Error>().map(ToString::to_string),
+            Some(expected.to_string())
+        );
+    }
+    assert_eq!(listen_key(&stream), key);
+    Ok(())
+}
+
+#[tokio::test]
+async fn restarts_on_keepalive_failure() -> Fallible<()> {
+    let (server, binance) = setup().await?;
+    let mut stream = binance.user_data_stream(UserStreamKind::Spot).await?;
+    let old = listen_key(&stream);
+
+    server.fail_next(
+        "/api/v3/userDataStream",
+        400,
+        -1125,
+        "This listenKey does not exist.",
+    );
+    time::pause();
+    time::advance(KEEPALIVE_INTERVAL).await;
+    let items = poll_until(&mut stream, restarted(old.clone())).await;
+    assert!(items.is_empty());
+    closed(&binance, &old).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn retries_failed_restart() -> Fallible<()> {
+    let (server, binance) = setup().await?;
+    let mut stream = binance.user_data_stream(UserStreamKind::Spot).await?;
+    let old = listen_key(&stream);
+
+    expire(&server, &old);
+    stream.try_next().await?;
+    // Only fail the new listen key once the old one is closed
+    closed(&binance, &old).await?;
+    server.fail_next("/api/v3/userDataStream", 500, -1001, "Internal error.");
+
+    time::pause();
+    let items = poll_until(&mut stream, |_, items| !items.is_empty()).await;
+    match items.as_slice() {
+        [Err(e)] => assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::BinanceError { code: -1001, .. })
+        )),
+        other => panic!("unexpected items {:?}", other),
+    }
+    assert!(stream.listen_key().is_none());
+
+    // The next attempt waits out the retry delay
+    time::advance(RESTART_DELAY).await;
+    let items = poll_until(&mut stream, restarted(old.clone())).await;
+    assert!(items.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn closes_on_drop() -> Fallible<()> {
+    let (_server, binance) = setup().await?;
+    let stream = binance.user_data_stream(UserStreamKind::Spot).await?;
+    let listen_key = listen_key(&stream);
+
+    drop(stream);
+    closed(&binance, &listen_key).await
+}